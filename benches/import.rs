@@ -0,0 +1,53 @@
+//! Import time of a model single-threaded and on all cores.
+//!
+//! `cargo bench --bench import [-- path/to/model.gltf]`, Sponza by default.
+
+use std::time::{Duration, Instant};
+
+use color_eyre::Result;
+use wgltf::{
+    app::PreparedModel,
+    gltf::{GltfDocument, LoadProgress},
+    utils::worker_count,
+};
+
+const DEFAULT_MODEL: &str = "assets/sponza-optimized/Sponza.gltf";
+const ITERATIONS: usize = 5;
+
+fn import(path: &str) -> Result<Duration> {
+    let start = Instant::now();
+    let progress = LoadProgress::default();
+    let gltf = GltfDocument::import_with_progress(path, &progress)?;
+    PreparedModel::new(gltf, &progress)?;
+    Ok(start.elapsed())
+}
+
+fn bench(path: &str, threads: usize) -> Result<Duration> {
+    std::env::set_var("WGLTF_THREADS", threads.to_string());
+    // Warm up the file cache
+    import(path)?;
+    let mut best = Duration::MAX;
+    for _ in 0..ITERATIONS {
+        best = best.min(import(path)?);
+    }
+    println!("{path}: {threads:>2} thread(s) {best:?}");
+    Ok(best)
+}
+
+fn main() -> Result<()> {
+    color_eyre::install()?;
+    // `cargo bench` passes `--bench` along with the user arguments
+    let path = std::env::args()
+        .skip(1)
+        .find(|arg| !arg.starts_with("--"))
+        .unwrap_or_else(|| DEFAULT_MODEL.to_owned());
+
+    let threads = worker_count();
+    let serial = bench(&path, 1)?;
+    let parallel = bench(&path, threads)?;
+    println!(
+        "Speedup: {:.2}x",
+        serial.as_secs_f64() / parallel.as_secs_f64()
+    );
+    Ok(())
+}
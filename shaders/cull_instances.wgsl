@@ -0,0 +1,138 @@
+struct Culling {
+    // Frustum planes pointing inside, see `Frustum`
+    planes: array<vec4<f32>, 5>,
+    // Projection and view of the frame the Hi-Z pyramid was built from
+    occlusion_view_proj: mat4x4<f32>,
+    draw_count: u32,
+    instance_count: u32,
+    // Zero skips the occlusion test
+    hiz_levels: u32,
+};
+
+struct Instance {
+    world: mat4x4<f32>,
+    normal: mat3x3<f32>,
+};
+
+// Bounds are in the space of the node
+struct Draw {
+    min: vec3<f32>,
+    count: u32,
+    max: vec3<f32>,
+    first: u32,
+    base_vertex: i32,
+    first_instance: u32,
+    indexed: u32,
+};
+
+// `DrawIndexedIndirect`, or `DrawIndirect` with `first_instance` in place of `base_vertex`
+struct DrawArgs {
+    count: u32,
+    instance_count: atomic<u32>,
+    first: u32,
+    base_vertex: u32,
+    first_instance: u32,
+};
+
+@group(0) @binding(0) var<uniform> culling: Culling;
+@group(0) @binding(1) var<storage, read> instances: array<Instance>;
+@group(0) @binding(2) var<storage, read_write> instance_indices: array<u32>;
+@group(0) @binding(3) var<storage, read> draws: array<Draw>;
+@group(0) @binding(4) var<storage, read> draw_ids: array<u32>;
+@group(0) @binding(5) var<storage, read_write> args: array<DrawArgs>;
+@group(0) @binding(6) var<storage, read_write> visible_count: atomic<u32>;
+// Farthest depth of the previous frame, see hiz.wgsl
+@group(0) @binding(7) var hiz: texture_2d<f32>;
+
+@compute @workgroup_size(64)
+fn reset(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if index >= culling.draw_count {
+        return;
+    }
+    let draw = draws[index];
+    args[index].count = draw.count;
+    atomicStore(&args[index].instance_count, 0u);
+    args[index].first = draw.first;
+    if draw.indexed != 0u {
+        args[index].base_vertex = bitcast<u32>(draw.base_vertex);
+        args[index].first_instance = draw.first_instance;
+    } else {
+        args[index].base_vertex = draw.first_instance;
+        args[index].first_instance = 0u;
+    }
+}
+
+// Whether the world-space box is behind the depth of the previous frame
+fn is_occluded(center: vec3<f32>, extents: vec3<f32>) -> bool {
+    var min_uv = vec2(1.0);
+    var max_uv = vec2(0.0);
+    var nearest = 0.0;
+    for (var i = 0u; i < 8u; i++) {
+        let corner = center + extents * vec3(
+            select(-1.0, 1.0, (i & 1u) != 0u),
+            select(-1.0, 1.0, (i & 2u) != 0u),
+            select(-1.0, 1.0, (i & 4u) != 0u),
+        );
+        let clip = culling.occlusion_view_proj * vec4(corner, 1.0);
+        // Reaches behind the camera
+        if clip.w <= 0.0 {
+            return false;
+        }
+        let ndc = clip.xyz / clip.w;
+        let uv = ndc.xy * vec2(0.5, -0.5) + 0.5;
+        min_uv = min(min_uv, uv);
+        max_uv = max(max_uv, uv);
+        nearest = max(nearest, ndc.z);
+    }
+    min_uv = clamp(min_uv, vec2(0.0), vec2(1.0));
+    max_uv = clamp(max_uv, vec2(0.0), vec2(1.0));
+
+    // The level where the box covers about 2x2 texels
+    let pixels = (max_uv - min_uv) * vec2<f32>(textureDimensions(hiz));
+    let level = min(
+        i32(ceil(log2(max(max(pixels.x, pixels.y), 1.0)))),
+        i32(culling.hiz_levels) - 1,
+    );
+    let size = textureDimensions(hiz, level);
+    let first = vec2<i32>(min_uv * vec2<f32>(size));
+    let last = min(vec2<i32>(max_uv * vec2<f32>(size)), size - 1);
+    var farthest = 1.0;
+    for (var y = first.y; y <= last.y; y++) {
+        for (var x = first.x; x <= last.x; x++) {
+            farthest = min(farthest, textureLoad(hiz, vec2(x, y), level).r);
+        }
+    }
+    return nearest < farthest;
+}
+
+// Appends the visible instances to the range of their draw
+@compute @workgroup_size(64)
+fn cull(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if index >= culling.instance_count {
+        return;
+    }
+    let draw_id = draw_ids[index];
+    let draw = draws[draw_id];
+    let world = instances[index].world;
+
+    let center = (world * vec4((draw.min + draw.max) * 0.5, 1.0)).xyz;
+    let half_size = (draw.max - draw.min) * 0.5;
+    let extents = abs(world[0].xyz) * half_size.x
+        + abs(world[1].xyz) * half_size.y
+        + abs(world[2].xyz) * half_size.z;
+    for (var i = 0; i < 5; i++) {
+        let plane = culling.planes[i];
+        if dot(plane.xyz, center) + dot(abs(plane.xyz), extents) + plane.w < 0.0 {
+            return;
+        }
+    }
+    if culling.hiz_levels != 0u && is_occluded(center, extents) {
+        return;
+    }
+
+    atomicAdd(&visible_count, 1u);
+    let slot = atomicAdd(&args[draw_id].instance_count, 1u);
+    instance_indices[draw.first_instance + slot] = index;
+}
@@ -13,20 +13,58 @@ struct Camera {
 
 @group(0) @binding(0) var<uniform> un: Globals;
 @group(1) @binding(0) var<uniform> camera: Camera;
-@group(2) @binding(0) var<uniform> model: mat4x4<f32>;
+// World matrices of node and EXT_mesh_gpu_instancing transform combined
+struct Instance {
+    world: mat4x4<f32>,
+    normal: mat3x3<f32>,
+};
+@group(2) @binding(0) var<storage, read> instances: array<Instance>;
+// Indices into `instances`, compacted to the visible ones when culling on the GPU
+@group(2) @binding(1) var<storage, read> instance_indices: array<u32>;
 
 struct Material {
     base_color_factor: vec3<f32>,
     alpha_cutoff: f32,
+    specular_color_factor: vec3<f32>,
+    specular_factor: f32,
+    metallic_factor: f32,
+    roughness_factor: f32,
+    anisotropy_strength: f32,
+    anisotropy_rotation: f32,
+    iridescence_factor: f32,
+    iridescence_ior: f32,
+    iridescence_thickness_minimum: f32,
+    iridescence_thickness_maximum: f32,
+    normal_scale: f32,
 };
 @group(3) @binding(0) var<uniform> material : Material;
+#ifdef BASE_COLOR_TEXTURE
 @group(3) @binding(1) var base_color_texture : texture_2d<f32>;
+#endif
 @group(3) @binding(2) var material_sampler : sampler;
+#ifdef METALLIC_ROUGHNESS_TEXTURE
+@group(3) @binding(8) var metallic_roughness_texture : texture_2d<f32>;
+#endif
+#ifdef NORMAL_MAP
+@group(3) @binding(9) var normal_texture : texture_2d<f32>;
+#endif
+#ifdef KHR_MATERIALS_SPECULAR
+@group(3) @binding(3) var specular_texture : texture_2d<f32>;
+@group(3) @binding(4) var specular_color_texture : texture_2d<f32>;
+#endif
+#ifdef KHR_MATERIALS_IRIDESCENCE
+@group(3) @binding(5) var iridescence_texture : texture_2d<f32>;
+@group(3) @binding(6) var iridescence_thickness_texture : texture_2d<f32>;
+#endif
+#ifdef ANISOTROPY_TEXTURE
+@group(3) @binding(7) var anisotropy_texture : texture_2d<f32>;
+#endif
 
 struct VertexInput {
 	@location(0) pos: vec3<f32>,
 	@location(1) normal: vec3<f32>,
 	@location(2) tex_coords: vec2<f32>,
+	@location(7) color: vec4<f32>,
 }
 
 struct VertexOutput {
@@ -35,61 +73,386 @@ struct VertexOutput {
 	@location(1) tex_coords: vec2<f32>,
 	@location(2) light_vec: vec3<f32>,
 	@location(3) view_vec: vec3<f32>,
+	@location(4) world_pos: vec3<f32>,
+	@location(5) color: vec4<f32>,
 }
 
 @vertex
-fn vs_main(in: VertexInput) -> VertexOutput {
+fn vs_main(in: VertexInput, @builtin(instance_index) instance_index: u32) -> VertexOutput {
     let LIGHT_POS = vec3(15., 10.5, 15.);
 
+    let instance = instances[instance_indices[instance_index]];
+    let model = instance.world;
+
     let vpos = camera.proj * camera.view * model * vec4(in.pos, 1.0);
     let pos = camera.view * model * vec4(in.pos, 1.0);
-    let normal = normalize((model * vec4(in.normal, 0.0)).xyz);
+    var normal = normalize(instance.normal * in.normal);
     let tex_coords = in.tex_coords;
     var light_vec = LIGHT_POS - pos.xyz;
     // light_vec = (model * vec4(light_vec, 1.0)).rgb;
     var view_vec = camera.position - pos.xyz;
     view_vec = (model * vec4(view_vec, 1.0)).rgb;
+    // Point clouds without normals face the viewer
+    if dot(in.normal, in.normal) == 0.0 {
+        normal = normalize(view_vec);
+    }
+
+    let world_pos = (model * vec4(in.pos, 1.0)).xyz;
+
+    return VertexOutput(vpos, normal, tex_coords, light_vec, view_vec, world_pos, in.color);
+}
+
+// Per-pixel tangent frame from screen-space derivatives, no vertex tangents required.
+fn cotangent_frame(nor: vec3<f32>, pos: vec3<f32>, uv: vec2<f32>) -> mat3x3<f32> {
+    let dp1 = dpdx(pos);
+    let dp2 = dpdy(pos);
+    let duv1 = dpdx(uv);
+    let duv2 = dpdy(uv);
+
+    let dp2perp = cross(dp2, nor);
+    let dp1perp = cross(nor, dp1);
+    let t = dp2perp * duv1.x + dp1perp * duv2.x;
+    let b = dp2perp * duv1.y + dp1perp * duv2.y;
 
-    return VertexOutput(vpos, normal, tex_coords, light_vec, view_vec);
+    let invmax = inverseSqrt(max(max(dot(t, t), dot(b, b)), 1e-12));
+    return mat3x3(t * invmax, b * invmax, nor);
 }
 
-fn shade(nor: vec3<f32>, light_dir: vec3<f32>, view: vec3<f32>, material_texture: vec4<f32>) -> vec3<f32> {
-    let refl = reflect(light_dir, nor);
-    let spec = pow(max(dot(refl, view), 0.0), 16.0) * vec3(0.015);
+#ifdef NORMAL_MAP
+fn perturb_normal(nor: vec3<f32>, pos: vec3<f32>, uv: vec2<f32>) -> vec3<f32> {
+    let tbn = cotangent_frame(nor, pos, uv);
+    let sample = textureSample(normal_texture, material_sampler, uv).rgb * 2.0 - 1.0;
+    return normalize(tbn * vec3(sample.xy * material.normal_scale, sample.z));
+}
+#endif
+
+// Metallic in x and roughness in y, factors included
+fn metallic_roughness(uv: vec2<f32>) -> vec2<f32> {
+    var metallic_roughness = vec2(material.metallic_factor, material.roughness_factor);
+#ifdef METALLIC_ROUGHNESS_TEXTURE
+    metallic_roughness *= textureSample(metallic_roughness_texture, material_sampler, uv).bg;
+#endif
+    return metallic_roughness;
+}
+
+#ifdef MATERIAL_EXTENSIONS
+const PI = 3.14159265359;
+
+fn f_schlick(f0: vec3<f32>, f90: vec3<f32>, v_dot_h: f32) -> vec3<f32> {
+    return f0 + (f90 - f0) * pow(clamp(1.0 - v_dot_h, 0.0, 1.0), 5.0);
+}
+
+fn d_ggx(n_dot_h: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let f = n_dot_h * n_dot_h * (a2 - 1.0) + 1.0;
+    return a2 / (PI * f * f);
+}
+
+fn v_ggx(n_dot_l: f32, n_dot_v: f32, alpha: f32) -> f32 {
+    let a2 = alpha * alpha;
+    let ggxv = n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0 - a2) + a2);
+    let ggxl = n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0 - a2) + a2);
+    let ggx = ggxv + ggxl;
+    return select(0.0, 0.5 / ggx, ggx > 0.0);
+}
+
+#ifdef KHR_MATERIALS_ANISOTROPY
+fn d_ggx_anisotropic(n_dot_h: f32, t_dot_h: f32, b_dot_h: f32, at: f32, ab: f32) -> f32 {
+    let a2 = at * ab;
+    let f = vec3(ab * t_dot_h, at * b_dot_h, a2 * n_dot_h);
+    let w2 = a2 / dot(f, f);
+    return a2 * w2 * w2 / PI;
+}
+
+fn v_ggx_anisotropic(
+    n_dot_l: f32,
+    n_dot_v: f32,
+    b_dot_v: f32,
+    t_dot_v: f32,
+    t_dot_l: f32,
+    b_dot_l: f32,
+    at: f32,
+    ab: f32,
+) -> f32 {
+    let ggxv = n_dot_l * length(vec3(at * t_dot_v, ab * b_dot_v, n_dot_v));
+    let ggxl = n_dot_v * length(vec3(at * t_dot_l, ab * b_dot_l, n_dot_l));
+    return clamp(0.5 / (ggxv + ggxl), 0.0, 1.0);
+}
+#endif
+
+#ifdef KHR_MATERIALS_IRIDESCENCE
+// Thin-film interference after Belcour and Barla 2017,
+// "A Practical Extension to Microfacet Theory for the Modeling of Varying Iridescence".
+fn iridescence_sensitivity(opd: f32, shift: vec3<f32>) -> vec3<f32> {
+    let phase = 2.0 * PI * opd * 1.0e-9;
+    let val = vec3(5.4856e-13, 4.4201e-13, 5.2481e-13);
+    let pos = vec3(1.6810e+06, 1.7953e+06, 2.2084e+06);
+    let var_ = vec3(4.3278e+09, 9.3046e+09, 6.6121e+09);
+
+    var xyz = val * sqrt(2.0 * PI * var_) * cos(pos * phase + shift) * exp(-phase * phase * var_);
+    xyz.x += 9.7470e-14 * sqrt(2.0 * PI * 4.5282e+09) * cos(2.2399e+06 * phase + shift[0])
+        * exp(-4.5282e+09 * phase * phase);
+    xyz /= 1.0685e-7;
+
+    let xyz_to_rgb = mat3x3(
+        vec3(3.2404542, -0.9692660, 0.0556434),
+        vec3(-1.5371385, 1.8760108, -0.2040259),
+        vec3(-0.4985314, 0.0415560, 1.0572252),
+    );
+    return xyz_to_rgb * xyz;
+}
+
+fn ior_to_fresnel0(transmitted_ior: vec3<f32>, incident_ior: f32) -> vec3<f32> {
+    let r = (transmitted_ior - incident_ior) / (transmitted_ior + incident_ior);
+    return r * r;
+}
+
+fn fresnel0_to_ior(f0: vec3<f32>) -> vec3<f32> {
+    let sqrt_f0 = sqrt(clamp(f0, vec3(0.0), vec3(0.9999)));
+    return (vec3(1.0) + sqrt_f0) / (vec3(1.0) - sqrt_f0);
+}
+
+fn eval_iridescence(eta2_in: f32, cos_theta1: f32, thickness: f32, base_f0: vec3<f32>) -> vec3<f32> {
+    // Film thickness of zero is the same as no film at all
+    let eta2 = mix(1.0, eta2_in, smoothstep(0.0, 0.03, thickness));
+    let sin_theta2_sq = (1.0 / (eta2 * eta2)) * (1.0 - cos_theta1 * cos_theta1);
+    let cos_theta2_sq = 1.0 - sin_theta2_sq;
+    if cos_theta2_sq < 0.0 {
+        // Total internal reflection
+        return vec3(1.0);
+    }
+    let cos_theta2 = sqrt(cos_theta2_sq);
+
+    // First interface, air to film
+    let r0 = ior_to_fresnel0(vec3(eta2), 1.0).x;
+    let r12 = r0 + (1.0 - r0) * pow(clamp(1.0 - cos_theta1, 0.0, 1.0), 5.0);
+    let t121 = 1.0 - r12;
+    let phi12 = select(0.0, PI, eta2 < 1.0);
+    let phi21 = PI - phi12;
+
+    // Second interface, film to base
+    let base_ior = fresnel0_to_ior(clamp(base_f0, vec3(0.0), vec3(0.9999)));
+    let r1 = ior_to_fresnel0(base_ior, eta2);
+    let r23 = r1 + (vec3(1.0) - r1) * pow(clamp(1.0 - cos_theta2, 0.0, 1.0), 5.0);
+    let phi23 = select(vec3(0.0), vec3(PI), base_ior < vec3(eta2));
+
+    // Phase shift
+    let opd = 2.0 * eta2 * thickness * cos_theta2;
+    let phi = vec3(phi21) + phi23;
+
+    // Compound terms
+    let r123 = clamp(r12 * r23, vec3(1e-5), vec3(0.9999));
+    let r123_sqrt = sqrt(r123);
+    let rs = (t121 * t121) * r23 / (vec3(1.0) - r123);
+
+    var i = vec3(r12) + rs;
+    var cm = rs - t121;
+    for (var m = 1; m <= 2; m++) {
+        cm *= r123_sqrt;
+        let sm = 2.0 * iridescence_sensitivity(f32(m) * opd, f32(m) * phi);
+        i += cm * sm;
+    }
+    return max(i, vec3(0.0));
+}
+#endif
+
+fn specular(
+    nor: vec3<f32>,
+    light_dir: vec3<f32>,
+    view: vec3<f32>,
+    pos: vec3<f32>,
+    uv: vec2<f32>,
+    base_color: vec3<f32>,
+    metallic: f32,
+    roughness: f32,
+) -> vec3<f32> {
+    let h = normalize(light_dir + view);
+    let n_dot_l = clamp(dot(nor, light_dir), 0.0, 1.0);
+    let n_dot_v = clamp(abs(dot(nor, view)), 1e-4, 1.0);
+    let n_dot_h = clamp(dot(nor, h), 0.0, 1.0);
+    let v_dot_h = clamp(dot(view, h), 0.0, 1.0);
+    let alpha = max(roughness * roughness, 1e-3);
+    let tbn = cotangent_frame(nor, pos, uv);
+
+    var f0 = vec3(0.04);
+    var specular_weight = 1.0;
+#ifdef KHR_MATERIALS_SPECULAR
+    let specular_sample = textureSample(specular_texture, material_sampler, uv).a;
+    let specular_color_sample = textureSample(specular_color_texture, material_sampler, uv).rgb;
+    f0 = min(f0 * material.specular_color_factor * specular_color_sample, vec3(1.0));
+    specular_weight = material.specular_factor * specular_sample;
+#endif
+    f0 = mix(f0, base_color, metallic);
+    specular_weight = mix(specular_weight, 1.0, metallic);
+
+    var fresnel = f_schlick(f0, vec3(1.0), v_dot_h);
+#ifdef KHR_MATERIALS_IRIDESCENCE
+    let iridescence = material.iridescence_factor
+        * textureSample(iridescence_texture, material_sampler, uv).r;
+    let thickness = mix(
+        material.iridescence_thickness_minimum,
+        material.iridescence_thickness_maximum,
+        textureSample(iridescence_thickness_texture, material_sampler, uv).g,
+    );
+    let iridescence_fresnel = eval_iridescence(material.iridescence_ior, n_dot_v, thickness, f0);
+    fresnel = mix(fresnel, iridescence_fresnel, iridescence);
+#endif
+
+#ifdef KHR_MATERIALS_ANISOTROPY
+#ifdef ANISOTROPY_TEXTURE
+    let anisotropy_sample = textureSample(anisotropy_texture, material_sampler, uv).rgb;
+#else
+    // Along the tangent at full strength
+    let anisotropy_sample = vec3(1.0, 0.5, 1.0);
+#endif
+    let rotation = vec2(cos(material.anisotropy_rotation), sin(material.anisotropy_rotation));
+    let direction = anisotropy_sample.rg * 2.0 - 1.0;
+    let rotated = vec2(
+        rotation.x * direction.x - rotation.y * direction.y,
+        rotation.y * direction.x + rotation.x * direction.y,
+    );
+    let strength = material.anisotropy_strength * anisotropy_sample.b;
+    let t = normalize(tbn * vec3(rotated, 0.0));
+    let b = cross(nor, t);
+    let at = mix(alpha, 1.0, strength * strength);
+    let ab = alpha;
+    let d = d_ggx_anisotropic(n_dot_h, dot(t, h), dot(b, h), at, ab);
+    let vis = v_ggx_anisotropic(
+        n_dot_l,
+        n_dot_v,
+        dot(b, view),
+        dot(t, view),
+        dot(t, light_dir),
+        dot(b, light_dir),
+        at,
+        ab,
+    );
+#else
+    let d = d_ggx(n_dot_h, alpha);
+    let vis = v_ggx(n_dot_l, n_dot_v, alpha);
+#endif
+
+    return specular_weight * fresnel * d * vis * n_dot_l;
+}
+
+fn shade(
+    nor: vec3<f32>,
+    light_dir: vec3<f32>,
+    view: vec3<f32>,
+    pos: vec3<f32>,
+    uv: vec2<f32>,
+    material_texture: vec4<f32>,
+) -> vec3<f32> {
+    let base_color = material_texture.rgb * material.base_color_factor;
+    let metallic_roughness = metallic_roughness(uv);
+    let metallic = metallic_roughness.x;
+    let roughness = metallic_roughness.y;
+    let spec = specular(nor, light_dir, view, pos, uv, base_color, metallic, roughness);
 
     let shade = dot(nor, light_dir);
-    let diff = mix(max(shade, 0.0), shade * 0.5 + 0.5, 0.25) * material.base_color_factor;
-    var surface_color = material_texture.rgb * diff + spec;
+    let diff = mix(max(shade, 0.0), shade * 0.5 + 0.5, 0.25) * (1.0 - metallic);
+    var surface_color = base_color * diff + spec;
 
     return surface_color;
 }
+#else
+fn shade(
+    nor: vec3<f32>,
+    light_dir: vec3<f32>,
+    view: vec3<f32>,
+    pos: vec3<f32>,
+    uv: vec2<f32>,
+    material_texture: vec4<f32>,
+) -> vec3<f32> {
+    let base_color = material_texture.rgb * material.base_color_factor;
+    let metallic_roughness = metallic_roughness(uv);
+    let metallic = metallic_roughness.x;
+    let roughness = metallic_roughness.y;
 
-@fragment
-fn fs_main_cutoff(vout: VertexOutput) -> @location(0) vec4<f32> {
-    let material_texture = textureSample(base_color_texture, material_sampler, vout.tex_coords);
+    // Normalized Blinn-Phong with the exponent of a GGX lobe of the same roughness
+    let alpha = max(roughness * roughness, 1e-3);
+    let exponent = 2.0 / (alpha * alpha) - 2.0;
+    let h = normalize(light_dir + view);
+    let n_dot_h = max(dot(nor, h), 1e-4);
+    let f0 = mix(vec3(0.04), base_color, metallic);
+    let n_dot_l = max(dot(nor, light_dir), 0.0);
+    let spec = f0 * (exponent + 8.0) / 8.0 * pow(n_dot_h, exponent) * n_dot_l;
 
-    if material_texture.a < material.alpha_cutoff {
-        discard;
-    }
+    let shade = dot(nor, light_dir);
+    let diff = mix(max(shade, 0.0), shade * 0.5 + 0.5, 0.25) * (1.0 - metallic);
+    var surface_color = base_color * diff + spec;
 
-    let nor = normalize(vout.normal);
-    let light_dir = normalize(vout.light_vec);
-    let view = normalize(vout.view_vec);
+    return surface_color;
+}
+#endif
 
-    let surface_color = shade(nor, light_dir, view, material_texture);
+#ifdef WEIGHTED_BLENDED_OIT
+struct FragmentOutput {
+	@location(0) accum: vec4<f32>,
+	@location(1) revealage: f32,
+}
 
-    return vec4(surface_color, material_texture.a);
+// Favors close and opaque layers, equation 7 of McGuire and Bavoil with reversed depth
+fn oit_weight(depth: f32, alpha: f32) -> f32 {
+    let z = 1.0 - depth;
+    let weight = pow(min(1.0, alpha * 10.0) + 0.01, 3.0) * 1e8 * pow(1.0 - z * 0.9, 3.0);
+    return clamp(weight, 1e-2, 3e3);
 }
+#else
+struct FragmentOutput {
+	@location(0) color: vec4<f32>,
+}
+#endif
 
+// Variants by alpha mode: OPAQUE ignores alpha, MASK discards below the cutoff or
+// turns it into coverage and BLEND keeps it
 @fragment
-fn fs_main(vout: VertexOutput) -> @location(0) vec4<f32> {
-    let material_texture = textureSample(base_color_texture, material_sampler, vout.tex_coords);
+fn fs_main(
+    vout: VertexOutput,
+    @builtin(front_facing) front_facing: bool,
+) -> FragmentOutput {
+    var material_texture = vout.color;
+#ifdef BASE_COLOR_TEXTURE
+    material_texture *= textureSample(base_color_texture, material_sampler, vout.tex_coords);
+#endif
+
+    var alpha = 1.0;
+#ifdef ALPHA_MASK
+#ifdef ALPHA_TO_COVERAGE
+    // Sharpened to a one pixel wide ramp around the cutoff, the coverage mask does the rest
+    alpha = clamp(
+        (material_texture.a - material.alpha_cutoff) / max(fwidth(material_texture.a), 1e-4) + 0.5,
+        0.0,
+        1.0,
+    );
+#else
+    if material_texture.a < material.alpha_cutoff {
+        discard;
+    }
+#endif
+#endif
+#ifdef ALPHA_BLEND
+    alpha = material_texture.a;
+#endif
 
-    let nor = normalize(vout.normal);
+    var nor = normalize(vout.normal);
+#ifdef DOUBLE_SIDED
+    // Back faces are lit from their own side, the cotangent frame built from
+    // the flipped normal flips its tangent and bitangent along with it
+    nor = select(-nor, nor, front_facing);
+#endif
+#ifdef NORMAL_MAP
+    nor = perturb_normal(nor, vout.world_pos, vout.tex_coords);
+#endif
     let light_dir = normalize(vout.light_vec);
     let view = normalize(vout.view_vec);
 
-    let surface_color = shade(nor, light_dir, view, material_texture);
+    let surface_color = shade(nor, light_dir, view, vout.world_pos, vout.tex_coords, material_texture);
 
-    return vec4(surface_color, material_texture.a);
+#ifdef WEIGHTED_BLENDED_OIT
+    let weight = oit_weight(vout.pos.z, alpha);
+    return FragmentOutput(vec4(surface_color * alpha, alpha) * weight, alpha);
+#else
+    return FragmentOutput(vec4(surface_color, alpha));
+#endif
 }
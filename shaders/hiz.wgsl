@@ -0,0 +1,42 @@
+// Farthest depth of every texel, with reverse-Z that's the smallest value
+
+// `App::SAMPLE_COUNT`
+const SAMPLE_COUNT = 4;
+
+@group(0) @binding(0) var depth: texture_multisampled_2d<f32>;
+@group(0) @binding(1) var source: texture_2d<f32>;
+@group(0) @binding(2) var destination: texture_storage_2d<r32float, write>;
+
+// First level from all samples of the depth buffer
+@compute @workgroup_size(8, 8)
+fn init(@builtin(global_invocation_id) id: vec3<u32>) {
+    let coords = vec2<i32>(id.xy);
+    if any(coords >= textureDimensions(destination)) {
+        return;
+    }
+    var farthest = 1.0;
+    for (var i = 0; i < SAMPLE_COUNT; i++) {
+        farthest = min(farthest, textureLoad(depth, coords, i).r);
+    }
+    textureStore(destination, coords, vec4(farthest));
+}
+
+// Halves the previous level, odd sizes take the extra row and column along
+@compute @workgroup_size(8, 8)
+fn downsample(@builtin(global_invocation_id) id: vec3<u32>) {
+    let coords = vec2<i32>(id.xy);
+    let size = textureDimensions(destination);
+    if any(coords >= size) {
+        return;
+    }
+    let source_size = textureDimensions(source);
+    let first = coords * 2;
+    let last = select(first + 1, source_size - 1, coords == size - 1);
+    var farthest = 1.0;
+    for (var y = first.y; y <= last.y; y++) {
+        for (var x = first.x; x <= last.x; x++) {
+            farthest = min(farthest, textureLoad(source, vec2(x, y), 0).r);
+        }
+    }
+    textureStore(destination, coords, vec4(farthest));
+}
@@ -0,0 +1,26 @@
+struct VertexOutput {
+    @builtin(position) position: vec4<f32>,
+};
+
+@vertex
+fn vs_main(@builtin(vertex_index) vertex_idx: u32) -> VertexOutput {
+    let uv = vec2(f32(vertex_idx & 2u), f32((vertex_idx << 1u) & 2u));
+    let pos = vec4(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    return VertexOutput(pos);
+}
+
+@group(0) @binding(0) var accum_texture: texture_2d<f32>;
+@group(0) @binding(1) var revealage_texture: texture_2d<f32>;
+
+// Weighted average of the transparent surfaces, blended over the opaque image
+@fragment
+fn fs_main(vout: VertexOutput) -> @location(0) vec4<f32> {
+    let coords = vec2<i32>(vout.position.xy);
+    let revealage = textureLoad(revealage_texture, coords, 0).r;
+    if revealage >= 1.0 {
+        discard;
+    }
+    let accum = textureLoad(accum_texture, coords, 0);
+    let average = accum.rgb / clamp(accum.a, 1e-4, 5e4);
+    return vec4(average, 1.0 - revealage);
+}
@@ -1,9 +1,20 @@
 use std::{
-    borrow::Cow, cell::RefCell, collections::HashMap, fmt::Display, iter::zip, num::NonZeroU32,
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    collections::{hash_map::Entry, HashMap},
+    fmt::Display,
+    hash::{Hash, Hasher},
+    num::NonZeroU32,
+    ops::Range,
+    path::Path,
 };
 
-use color_eyre::{eyre::ContextCompat, Result};
+use color_eyre::{
+    eyre::{bail, ensure, eyre, ContextCompat},
+    Result,
+};
 use glam::vec4;
+use log::{info, warn};
 use pollster::FutureExt;
 use wgpu::{util::DeviceExt, FilterMode};
 use wgpu_profiler::{scope::Scope, GpuProfiler};
@@ -11,8 +22,12 @@ use winit::{dpi::PhysicalSize, window::Window};
 
 use crate::{
     camera::CameraBinding,
-    gltf::{convert_sampler, mesh_mode_to_topology, GltfDocument},
-    utils::{create_solid_color_texture, NonZeroSized, UnwrapRepeat},
+    gltf::{
+        convert_sampler, export, mesh_mode_to_topology, texture_sources, GltfDocument, Image, Ktx2,
+        LoadProgress, MaterialExtensions,
+    },
+    mesh::{MeshDocument, MeshMaterial},
+    utils::create_solid_color_texture,
 };
 
 pub(crate) const DEFAULT_SAMPLER_DESC: wgpu::SamplerDescriptor<'static> = wgpu::SamplerDescriptor {
@@ -24,16 +39,42 @@ pub(crate) const DEFAULT_SAMPLER_DESC: wgpu::SamplerDescriptor<'static> = wgpu::
     min_filter: FilterMode::Linear,
     mipmap_filter: FilterMode::Linear,
     lod_min_clamp: 0.0,
-    lod_max_clamp: std::f32::MAX,
+    lod_max_clamp: f32::MAX,
     compare: None,
     anisotropy_clamp: None,
     border_color: None,
 };
 
 mod blitter;
+mod culling;
+mod geometry;
 mod global_ubo;
+mod gpu_culling;
+mod hiz;
+mod instances;
+mod loader;
+mod material;
+mod model;
+mod oit;
+mod preprocessor;
+mod render_queue;
+mod scene;
 mod state;
 use blitter::Blitter;
+use culling::Frustum;
+pub use culling::{CullStats, CullingMode};
+use geometry::GeometryArena;
+pub use geometry::Indices;
+use gpu_culling::{GpuCulling, GpuDraw};
+use instances::{InstanceBinding, InstanceTransform};
+pub use loader::{BakedMaterial, LoadedModel, ModelLoader, PreparedModel, PreparedPrimitive};
+pub use material::{MaterialFeatures, MaterialUniform};
+pub use model::{Bounds, MaterialInfo, MaterialKey, Model, ModelId, VariantMapping};
+pub use oit::TransparencyMode;
+use oit::WeightedBlendedOit;
+pub use preprocessor::preprocess;
+use render_queue::{DrawItem, RenderQueue, SortKey};
+pub use scene::{Scene, SceneImage, SceneMesh, SceneNode, ScenePrimitive};
 pub use state::AppState;
 
 #[repr(C)]
@@ -42,25 +83,69 @@ pub struct MeshVertex {
     pub position: [f32; 3],
     pub normal: [f32; 3],
     pub tex_coord: [f32; 2],
+    /// Multiplies the base color
+    pub color: [f32; 4],
 }
 
-#[derive(Debug, Eq, PartialEq, Hash, Clone)]
+/// Everything a mesh pipeline is specialized on, the shader variant is picked with defines.
+#[derive(Debug, Eq, PartialEq, Clone)]
 pub struct PipelineArgs {
     pub topology: wgpu::PrimitiveTopology,
     pub target_format: wgpu::TextureFormat,
-    pub cull_mode: Option<wgpu::Face>,
-    pub blend: Option<wgpu::BlendState>,
+    pub double_sided: bool,
+    pub alpha_mode: gltf::material::AlphaMode,
+    /// Only for MASK materials, replaces the cutoff discard
+    pub alpha_to_coverage: bool,
+    /// Only for BLEND materials, draws to the accumulation and revealage targets
+    pub weighted_blended_oit: bool,
+    pub features: MaterialFeatures,
+}
+
+impl Hash for PipelineArgs {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.topology.hash(state);
+        self.target_format.hash(state);
+        self.double_sided.hash(state);
+        (self.alpha_mode as u8).hash(state);
+        self.alpha_to_coverage.hash(state);
+        self.weighted_blended_oit.hash(state);
+        self.features.hash(state);
+    }
 }
 
 impl PipelineArgs {
     pub fn new(
         topology: wgpu::PrimitiveTopology,
         target_format: wgpu::TextureFormat,
-        double_sided: bool,
-        alpha_mode: gltf::material::AlphaMode,
+        material: &MaterialInfo,
+        alpha_to_coverage: bool,
+        transparency: TransparencyMode,
     ) -> Self {
-        let cull_mode = (!double_sided).then_some(wgpu::Face::Back);
-        let blend = (alpha_mode == gltf::material::AlphaMode::Blend).then_some(wgpu::BlendState {
+        Self {
+            topology,
+            target_format,
+            double_sided: material.double_sided,
+            alpha_mode: material.alpha_mode,
+            alpha_to_coverage: alpha_to_coverage
+                && material.alpha_mode == gltf::material::AlphaMode::Mask,
+            weighted_blended_oit: transparency == TransparencyMode::WeightedBlended
+                && material.alpha_mode == gltf::material::AlphaMode::Blend,
+            features: material.features,
+        }
+    }
+
+    pub fn cull_mode(&self) -> Option<wgpu::Face> {
+        (!self.double_sided).then_some(wgpu::Face::Back)
+    }
+
+    /// Blended surfaces are sorted instead of depth tested against each other, and must not
+    /// hide what's behind them from the Hi-Z pyramid of occlusion culling.
+    pub fn depth_write(&self) -> bool {
+        self.alpha_mode != gltf::material::AlphaMode::Blend
+    }
+
+    pub fn blend(&self) -> Option<wgpu::BlendState> {
+        (self.alpha_mode == gltf::material::AlphaMode::Blend).then_some(wgpu::BlendState {
             color: wgpu::BlendComponent {
                 src_factor: wgpu::BlendFactor::SrcAlpha,
                 dst_factor: wgpu::BlendFactor::OneMinusSrcAlpha,
@@ -71,13 +156,26 @@ impl PipelineArgs {
                 dst_factor: wgpu::BlendFactor::One,
                 operation: wgpu::BlendOperation::Add,
             },
-        });
-        Self {
-            topology,
-            target_format,
-            cull_mode,
-            blend,
+        })
+    }
+
+    pub fn shader_defines(&self) -> Vec<&'static str> {
+        let mut defines = self.features.shader_defines();
+        match self.alpha_mode {
+            gltf::material::AlphaMode::Opaque => {}
+            gltf::material::AlphaMode::Mask => defines.push("ALPHA_MASK"),
+            gltf::material::AlphaMode::Blend => defines.push("ALPHA_BLEND"),
+        }
+        if self.alpha_to_coverage {
+            defines.push("ALPHA_TO_COVERAGE");
+        }
+        if self.weighted_blended_oit {
+            defines.push("WEIGHTED_BLENDED_OIT");
         }
+        if self.double_sided {
+            defines.push("DOUBLE_SIDED");
+        }
+        defines
     }
 }
 
@@ -85,8 +183,16 @@ impl Display for PipelineArgs {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "{{ {:?}, {:?}, {:?}, {:?} }}",
-            self.topology, self.target_format, self.cull_mode, self.blend
+            "{{ {:?}, {:?}, {}, {:?}, {:?} }}",
+            self.topology,
+            self.target_format,
+            if self.double_sided {
+                "DoubleSided"
+            } else {
+                "SingleSided"
+            },
+            self.alpha_mode,
+            self.shader_defines()
         )
     }
 }
@@ -96,14 +202,27 @@ pub fn create_mesh_pipeline(
     layout: &wgpu::PipelineLayout,
     args: &PipelineArgs,
 ) -> wgpu::RenderPipeline {
-    let attributes = &wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x3, 2 => Float32x2];
+    let attributes = &wgpu::vertex_attr_array![
+        0 => Float32x3, 1 => Float32x3, 2 => Float32x2, 7 => Float32x4
+    ];
+    let source = include_str!(concat!(
+        env!("CARGO_MANIFEST_DIR"),
+        "/shaders/draw_mesh.wgsl"
+    ));
     let shader_module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
         label: Some("Mesh Shader"),
-        source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!(concat!(
-            env!("CARGO_MANIFEST_DIR"),
-            "/shaders/draw_mesh.wgsl"
-        )))),
+        source: wgpu::ShaderSource::Wgsl(Cow::Owned(preprocess(source, &args.shader_defines()))),
     });
+    let target = [Some(wgpu::ColorTargetState {
+        format: args.target_format,
+        blend: args.blend(),
+        write_mask: wgpu::ColorWrites::ALL,
+    })];
+    let targets: &[_] = if args.weighted_blended_oit {
+        &oit::OIT_TARGETS
+    } else {
+        &target
+    };
     device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
         label: Some(&format!("Pipeline: {}", args)),
         layout: Some(layout),
@@ -118,57 +237,115 @@ pub fn create_mesh_pipeline(
         },
         primitive: wgpu::PrimitiveState {
             topology: args.topology,
-            cull_mode: args.cull_mode,
+            cull_mode: args.cull_mode(),
             ..Default::default()
         },
         fragment: Some(wgpu::FragmentState {
             module: &shader_module,
-            entry_point: if args.cull_mode.is_some() {
-                "fs_main"
-            } else {
-                "fs_main_cutoff"
-            },
-            targets: &[Some(wgpu::ColorTargetState {
-                format: args.target_format,
-                blend: args.blend,
-                write_mask: wgpu::ColorWrites::ALL,
-            })],
+            entry_point: "fs_main",
+            targets,
         }),
         depth_stencil: Some(wgpu::DepthStencilState {
             format: App::DEPTH_FORMAT,
-            depth_write_enabled: true,
+            depth_write_enabled: args.depth_write(),
             depth_compare: wgpu::CompareFunction::GreaterEqual,
             stencil: wgpu::StencilState::default(),
             bias: wgpu::DepthBiasState::default(),
         }),
         multisample: wgpu::MultisampleState {
             count: App::SAMPLE_COUNT,
+            alpha_to_coverage_enabled: args.alpha_to_coverage,
             ..Default::default()
         },
         multiview: None,
     })
 }
 
+/// Texture views of a material, missing ones bind opaque white.
+#[derive(Default)]
+struct MaterialViews<'a> {
+    base_color: Option<&'a wgpu::TextureView>,
+    metallic_roughness: Option<&'a wgpu::TextureView>,
+    normal: Option<&'a wgpu::TextureView>,
+    specular: Option<&'a wgpu::TextureView>,
+    specular_color: Option<&'a wgpu::TextureView>,
+    iridescence: Option<&'a wgpu::TextureView>,
+    iridescence_thickness: Option<&'a wgpu::TextureView>,
+    anisotropy: Option<&'a wgpu::TextureView>,
+}
+
 #[derive(Debug)]
 pub enum DrawMode {
-    Normal(u32),
+    Normal,
+    /// Range in the index arena of `format`, indices are relative to the first vertex of
+    /// the primitive
     Indexed {
-        buffer: wgpu::Buffer,
-        draw_count: u32,
+        indices: Range<u32>,
+        format: wgpu::IndexFormat,
     },
 }
 
 #[derive(Debug)]
 pub struct Primitive {
-    pub buffer: wgpu::Buffer,
-    pub instances: Vec<wgpu::BindGroup>,
+    /// Mesh and primitive index in the source document
+    pub source: (usize, usize),
+    /// Range in the vertex arena
+    pub vertices: Range<u32>,
+    /// In the space of the node
+    pub bounds: Bounds,
+    /// Nodes of the model's [`Scene`] drawing the primitive
+    pub nodes: Vec<usize>,
+    /// Transforms of all nodes and their instances in the instance buffer
+    pub instances: Range<u32>,
+    /// World-space bounds center, averaged over the instances
+    pub center: glam::Vec3,
+    /// World-space bounds of all instances
+    pub world_bounds: Bounds,
+    /// World-space bounds of every instance of a blend primitive, which draws its instances
+    /// one by one so they sort back to front with the rest. Empty otherwise.
+    pub instance_bounds: Vec<Bounds>,
+    /// Index of the indirect arguments written by GPU culling, the first of one slot per
+    /// instance for blend primitives
+    pub draw_slot: u32,
     pub draw_mode: DrawMode,
 }
 
+/// Instances of a [`Primitive`] drawn at once.
+pub struct PrimitiveDraw {
+    pub instances: Range<u32>,
+    pub draw_slot: u32,
+    pub world_bounds: Bounds,
+    pub center: glam::Vec3,
+}
+
+impl Primitive {
+    /// A single draw of all instances, or one per instance for blend primitives.
+    pub fn draws(&self) -> impl Iterator<Item = PrimitiveDraw> + '_ {
+        let all = self.instance_bounds.is_empty().then(|| PrimitiveDraw {
+            instances: self.instances.clone(),
+            draw_slot: self.draw_slot,
+            world_bounds: self.world_bounds,
+            center: self.center,
+        });
+        let single = self
+            .instances
+            .clone()
+            .zip(&self.instance_bounds)
+            .zip(self.draw_slot..)
+            .map(|((instance, &world_bounds), draw_slot)| PrimitiveDraw {
+                instances: instance..instance + 1,
+                draw_slot,
+                world_bounds,
+                center: world_bounds.center(),
+            });
+        all.into_iter().chain(single)
+    }
+}
+
 #[derive(Debug)]
 pub struct GpuPipeline {
     pub pipeline: wgpu::RenderPipeline,
-    pub primitives: HashMap<Option<usize>, Vec<Primitive>>,
+    pub primitives: HashMap<MaterialKey, Vec<Primitive>>,
 }
 
 pub struct App {
@@ -189,12 +366,22 @@ pub struct App {
 
     camera_binding: CameraBinding,
 
-    node_bind_group_layout: wgpu::BindGroupLayout,
+    instance_binding: InstanceBinding,
     material_bind_group_layout: wgpu::BindGroupLayout,
 
     pipeline_layout: wgpu::PipelineLayout,
     pipeline_data: HashMap<PipelineArgs, GpuPipeline>,
-    material_data: HashMap<Option<usize>, wgpu::BindGroup>,
+    geometry: GeometryArena,
+    /// MASK materials fade out over the samples of a pixel instead of discarding
+    alpha_to_coverage: bool,
+    transparency: TransparencyMode,
+    oit: WeightedBlendedOit,
+    culling: CullingMode,
+    /// `None` where the adapter can't draw indirectly with a first instance
+    gpu_culling: Option<GpuCulling>,
+    material_data: HashMap<MaterialKey, wgpu::BindGroup>,
+    models: HashMap<ModelId, Model>,
+    next_model_id: usize,
 
     default_sampler: wgpu::Sampler,
     opaque_white_texture: wgpu::Texture,
@@ -202,6 +389,7 @@ pub struct App {
     blitter: Blitter,
 
     profiler: RefCell<wgpu_profiler::GpuProfiler>,
+    cull_stats: Cell<CullStats>,
 }
 
 impl App {
@@ -247,6 +435,8 @@ impl App {
         let depth_texture = Self::create_depth_texture(&device, &surface_config);
         let multisampled_framebuffer =
             Self::create_multisampled_framebuffer(&device, &surface_config);
+        let oit = WeightedBlendedOit::new(&device, &surface_config);
+        let geometry = GeometryArena::new(&device);
 
         let camera_binding = CameraBinding::new(&device);
         let global_uniform_binding = global_ubo::GlobalUniformBinding::new(&device);
@@ -260,60 +450,24 @@ impl App {
             create_solid_color_texture(&device, &queue, vec4(1., 1., 1., 1.));
         let default_sampler = device.create_sampler(&DEFAULT_SAMPLER_DESC);
 
-        let node_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Node Bind Group Layout"),
-                entries: &[wgpu::BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: wgpu::ShaderStages::VERTEX,
-                    ty: wgpu::BindingType::Buffer {
-                        ty: wgpu::BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: Some(glam::Mat4::NSIZE),
-                    },
-                    count: None,
-                }],
-            });
+        let instance_binding = InstanceBinding::new(&device);
+        let gpu_culling =
+            GpuCulling::new(&device, &instance_binding, &depth_texture, &surface_config);
+        let mut culling = CullingMode::from_env();
+        if culling.is_gpu() && gpu_culling.is_none() {
+            warn!("GPU culling needs Features::INDIRECT_FIRST_INSTANCE, culling on the CPU");
+            culling = CullingMode::Cpu;
+        }
 
         let material_bind_group_layout =
-            device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
-                label: Some("Material Bind Group Layout"),
-                entries: &[
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Buffer {
-                            ty: wgpu::BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: Some(glam::Vec4::NSIZE),
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Texture {
-                            sample_type: wgpu::TextureSampleType::Float { filterable: true },
-                            view_dimension: wgpu::TextureViewDimension::D2,
-                            multisampled: false,
-                        },
-                        count: None,
-                    },
-                    wgpu::BindGroupLayoutEntry {
-                        binding: 2,
-                        visibility: wgpu::ShaderStages::FRAGMENT,
-                        ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
-                        count: None,
-                    },
-                ],
-            });
+            device.create_bind_group_layout(&material::MATERIAL_LAYOUT_DESC);
 
         let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Mesh Pipeline Layout"),
             bind_group_layouts: &[
                 &global_uniform_binding.layout,
                 &camera_binding.bind_group_layout,
-                &node_bind_group_layout,
+                &instance_binding.layout,
                 &material_bind_group_layout,
             ],
             push_constant_ranges: &[],
@@ -321,6 +475,7 @@ impl App {
 
         Ok(Self {
             profiler: RefCell::new(GpuProfiler::new(4, queue.get_timestamp_period(), features)),
+            cull_stats: Cell::default(),
             blitter: Blitter::new(&device),
             adapter,
             instance,
@@ -342,16 +497,24 @@ impl App {
             limits,
             features,
 
-            node_bind_group_layout,
+            instance_binding,
             material_bind_group_layout,
 
             pipeline_layout,
             pipeline_data: HashMap::new(),
+            geometry,
+            alpha_to_coverage: false,
+            transparency: TransparencyMode::from_env(),
+            oit,
+            culling,
+            gpu_culling,
             material_data: HashMap::new(),
+            models: HashMap::new(),
+            next_model_id: 0,
         })
     }
 
-    pub fn render(&self, _state: &AppState) -> Result<(), wgpu::SurfaceError> {
+    pub fn render(&self, state: &AppState) -> Result<(), wgpu::SurfaceError> {
         let mut profiler = self.profiler.borrow_mut();
         let target = self.surface.get_current_texture()?;
         let target_view = target.texture.create_view(&Default::default());
@@ -364,11 +527,75 @@ impl App {
 
         profiler.begin_scope("Main Render Scope ", &mut encoder, &self.device);
 
+        let (projection, view) = state.camera.build_projection_view_matrix();
+        let frustum = Frustum::from_projection_view(projection * view);
+        let gpu_culling = self.gpu_culling.as_ref().filter(|_| self.culling.is_gpu());
+        let occlusion = self.culling == CullingMode::GpuOcclusion;
+        if let Some(culling) = gpu_culling {
+            if let Some(stats) = culling.read_stats(&self.device) {
+                self.cull_stats.set(stats);
+            }
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Culling Pass"),
+            });
+            let mut scope = Scope::start("Culling", &mut profiler, &mut pass, &self.device);
+            culling.cull(&self.queue, &mut scope, &frustum, occlusion);
+            drop(scope);
+            drop(pass);
+            culling.copy_stats(&mut encoder);
+        }
+        let indirect = gpu_culling.map(GpuCulling::indirect);
+
+        let mut cull_stats = CullStats::default();
+        let mut queue = RenderQueue::default();
+        for pipeline in self.pipeline_data.values() {
+            for (material, primitives) in &pipeline.primitives {
+                let (model_id, material_index) = *material;
+                let alpha_mode = self.models[&model_id].materials[&material_index].alpha_mode;
+                for primitive in primitives {
+                    if primitive.instances.is_empty() {
+                        continue;
+                    }
+                    for draw in primitive.draws() {
+                        if indirect.is_none() {
+                            let instances = draw.instances.len();
+                            if !frustum.intersects(&draw.world_bounds) {
+                                cull_stats.culled += instances;
+                                continue;
+                            }
+                            cull_stats.visible += instances;
+                        }
+                        let item = DrawItem {
+                            pipeline: &pipeline.pipeline,
+                            material: &self.material_data[material],
+                            primitive,
+                            instances: draw.instances,
+                            key: SortKey {
+                                alpha_mode,
+                                distance: draw.center.distance_squared(state.camera.position),
+                                order: (model_id.0, primitive.source),
+                                draw_slot: draw.draw_slot,
+                            },
+                        };
+                        queue.push(item);
+                    }
+                }
+            }
+        }
+
+        if indirect.is_none() {
+            self.cull_stats.set(cull_stats);
+        }
+
+        let [opaque, mask, blend] = queue.into_buckets(indirect.is_some());
+        // Without transparent primitives the main pass resolves to the target as usual
+        let oit = self.transparency == TransparencyMode::WeightedBlended && !blend.1.is_empty();
+
         let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
             label: Some("Render Pass Descriptor"),
             color_attachments: &[Some(wgpu::RenderPassColorAttachment {
                 view: &self.multisampled_framebuffer,
-                resolve_target: Some(&target_view),
+                resolve_target: (!oit).then_some(&target_view),
                 ops: wgpu::Operations {
                     load: wgpu::LoadOp::Clear(wgpu::Color {
                         r: 0.13,
@@ -376,7 +603,7 @@ impl App {
                         b: 0.13,
                         a: 1.0,
                     }),
-                    store: false,
+                    store: oit,
                 },
             })],
             depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
@@ -391,44 +618,75 @@ impl App {
 
         pass.set_bind_group(0, &self.global_uniform_binding.binding, &[]);
         pass.set_bind_group(1, &self.camera_binding.binding, &[]);
+        pass.set_bind_group(2, &self.instance_binding.binding, &[]);
+
+        let mut buckets = vec![opaque, mask];
+        let transparent = if oit {
+            Some(blend)
+        } else {
+            buckets.push(blend);
+            None
+        };
+        for (name, items) in buckets {
+            let mut pass = Scope::start(name, &mut profiler, &mut pass, &self.device);
+            render_queue::draw_items(&mut pass, &self.geometry, items, indirect);
+        }
 
-        for (args, pipeline) in &self.pipeline_data {
-            let mut pass = Scope::start(&args.to_string(), &mut profiler, &mut pass, &self.device);
-            pass.set_pipeline(&pipeline.pipeline);
+        drop(pass);
 
-            for (material, primitives) in &pipeline.primitives {
-                pass.set_bind_group(3, &self.material_data[material], &[]);
-                for primitive in primitives {
-                    pass.set_vertex_buffer(0, primitive.buffer.slice(..));
-
-                    match &primitive.draw_mode {
-                        DrawMode::Normal(draw_count) => {
-                            // let mut pass = pass.scope("Draw", &self.device);
-                            for bind_group in &primitive.instances {
-                                pass.set_bind_group(2, bind_group, &[]);
-                                pass.draw(0..*draw_count, 0..1);
-                            }
-                        }
-                        DrawMode::Indexed { buffer, draw_count } => {
-                            // let mut pass = pass.scope("Draw Indexed", &self.device);
-                            pass.set_index_buffer(buffer.slice(..), wgpu::IndexFormat::Uint32);
-                            for bind_group in &primitive.instances {
-                                pass.set_bind_group(2, bind_group, &[]);
-                                pass.draw_indexed(0..*draw_count, 0, 0..1);
-                            }
-                        }
-                    }
-                }
-            }
+        if let Some(culling) = gpu_culling.filter(|_| occlusion) {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+                label: Some("Hi-Z Pass"),
+            });
+            let mut scope = Scope::start("Hi-Z", &mut profiler, &mut pass, &self.device);
+            culling.build_hiz(&mut scope, projection * view);
         }
 
-        drop(pass);
+        if let Some((name, items)) = transparent {
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("OIT Accumulation Pass"),
+                color_attachments: &self.oit.color_attachments(),
+                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                    view: &self.depth_texture,
+                    depth_ops: Some(wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: false,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            pass.set_bind_group(0, &self.global_uniform_binding.binding, &[]);
+            pass.set_bind_group(1, &self.camera_binding.binding, &[]);
+            pass.set_bind_group(2, &self.instance_binding.binding, &[]);
+            let mut scope = Scope::start(name, &mut profiler, &mut pass, &self.device);
+            render_queue::draw_items(&mut scope, &self.geometry, items, indirect);
+            drop(scope);
+            drop(pass);
+
+            let mut pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("OIT Composite Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &self.multisampled_framebuffer,
+                    resolve_target: Some(&target_view),
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Load,
+                        store: false,
+                    },
+                })],
+                depth_stencil_attachment: None,
+            });
+            let mut scope = Scope::start("Composite", &mut profiler, &mut pass, &self.device);
+            self.oit.composite(&mut scope);
+        }
 
         profiler.end_scope(&mut encoder);
 
         profiler.resolve_queries(&mut encoder);
 
         self.queue.submit(Some(encoder.finish()));
+        if let Some(culling) = gpu_culling {
+            culling.map_stats();
+        }
         target.present();
         profiler.end_frame().ok();
 
@@ -443,8 +701,17 @@ impl App {
         self.surface_config.height = height;
         self.surface.configure(&self.device, &self.surface_config);
         self.depth_texture = Self::create_depth_texture(&self.device, &self.surface_config);
+        if let Some(culling) = &mut self.gpu_culling {
+            culling.resize(
+                &self.device,
+                &self.instance_binding,
+                &self.depth_texture,
+                &self.surface_config,
+            );
+        }
         self.multisampled_framebuffer =
             Self::create_multisampled_framebuffer(&self.device, &self.surface_config);
+        self.oit.resize(&self.device, &self.surface_config);
         self.global_uniform.resolution = [width as f32, height as f32];
     }
 
@@ -455,233 +722,891 @@ impl App {
             .update(&self.queue, &self.global_uniform);
         self.camera_binding.update(&self.queue, &state.camera);
 
-        if state.frame_count % 100 == 0 {
+        if state.frame_count.is_multiple_of(100) {
             let mut last_profile = vec![];
             while let Some(profiling_data) = self.profiler.borrow_mut().process_finished_frame() {
                 last_profile = profiling_data;
             }
             crate::utils::scopes_to_console_recursive(&last_profile, 0);
+            let CullStats { visible, culled } = self.cull_stats.get();
+            println!("Instances visible: {visible}, culled: {culled}");
             println!();
         }
     }
 
-    pub fn add_gltf_model(&mut self, gltf: GltfDocument) -> Result<()> {
-        for material in gltf.document.materials() {
-            let pbr = material.pbr_metallic_roughness();
-            let mut color = pbr.base_color_factor();
-            color[3] = material.alpha_cutoff().unwrap_or(0.5);
-
-            let buffer = self
-                .device
-                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some(&format!("Material Color: {:?}", material.index())),
-                    contents: bytemuck::bytes_of(&color),
-                    usage: wgpu::BufferUsages::UNIFORM,
-                });
-            let bind_group = match pbr.base_color_texture().map(|t| t.texture()) {
-                Some(tex) => {
-                    let sampler = convert_sampler(&self.device, tex.sampler());
-                    let image = &gltf.images[tex.source().index()];
-                    let (width, height) = (image.width, image.height);
-                    let image = crate::gltf::convert_to_rgba(image)?;
-                    let mip_level_count = width.max(height).ilog2() + 1;
-                    let size = wgpu::Extent3d {
-                        width,
-                        height,
-                        depth_or_array_layers: 1,
-                    };
+    pub fn add_gltf_model(&mut self, gltf: GltfDocument) -> Result<ModelId> {
+        self.add_prepared_model(PreparedModel::new(gltf, &LoadProgress::default())?)
+    }
 
-                    let desc = wgpu::TextureDescriptor {
-                        label: None,
-                        size,
-                        mip_level_count,
-                        sample_count: 1,
-                        dimension: wgpu::TextureDimension::D2,
-                        format: wgpu::TextureFormat::Rgba8Unorm,
-                        usage: wgpu::TextureUsages::TEXTURE_BINDING
-                            | wgpu::TextureUsages::COPY_DST
-                            | wgpu::TextureUsages::RENDER_ATTACHMENT,
-
-                        view_formats: &[],
-                    };
-                    let texture = self.device.create_texture(&desc);
-                    self.queue.write_texture(
-                        wgpu::ImageCopyTextureBase {
-                            texture: &texture,
-                            mip_level: 0,
-                            origin: wgpu::Origin3d::ZERO,
-                            aspect: wgpu::TextureAspect::All,
-                        },
-                        image.as_raw(),
-                        wgpu::ImageDataLayout {
-                            offset: 0,
-                            bytes_per_row: NonZeroU32::new(width * 4),
-                            rows_per_image: None,
-                        },
-                        size,
-                    );
-                    let texture_view = texture.create_view(&Default::default());
-
-                    let mut encoder = self.device.create_command_encoder(&Default::default());
-                    self.blitter
-                        .generate_mipmaps(&self.device, &mut encoder, &texture);
-                    self.queue.submit(Some(encoder.finish()));
-
-                    self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-                        label: None,
-                        layout: &self.material_bind_group_layout,
-                        entries: &[
-                            wgpu::BindGroupEntry {
-                                binding: 0,
-                                resource: buffer.as_entire_binding(),
-                            },
-                            wgpu::BindGroupEntry {
-                                binding: 1,
-                                // TODO: Stick to always sRGB format... or not
-                                resource: wgpu::BindingResource::TextureView(&texture_view),
-                            },
-                            wgpu::BindGroupEntry {
-                                binding: 2,
-                                resource: wgpu::BindingResource::Sampler(&sampler),
-                            },
-                        ],
-                    })
-                }
-                None => {
-                    let texture_view = self.opaque_white_texture.create_view(&Default::default());
-                    self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-                        label: None,
-                        layout: &self.material_bind_group_layout,
-                        entries: &[
-                            wgpu::BindGroupEntry {
-                                binding: 0,
-                                resource: buffer.as_entire_binding(),
-                            },
-                            wgpu::BindGroupEntry {
-                                binding: 1,
-                                resource: wgpu::BindingResource::TextureView(&texture_view),
-                            },
-                            wgpu::BindGroupEntry {
-                                binding: 2,
-                                resource: wgpu::BindingResource::Sampler(&self.default_sampler),
-                            },
-                        ],
-                    })
-                }
-            };
+    /// Uploads a model prepared by [`ModelLoader`] or [`PreparedModel::new`].
+    pub fn add_prepared_model(&mut self, prepared: PreparedModel) -> Result<ModelId> {
+        let PreparedModel {
+            gltf,
+            primitives,
+            baked_materials,
+            ..
+        } = &prepared;
+        let model_id = self.next_model_id();
+        let mut model = Model {
+            variants: gltf
+                .document
+                .variants()
+                .into_iter()
+                .flatten()
+                .map(|variant| variant.name().to_owned())
+                .collect(),
+            ..Default::default()
+        };
 
+        let mut textures = HashMap::new();
+        let mut samplers = HashMap::new();
+        let default_material = gltf
+            .document
+            .meshes()
+            .flat_map(|mesh| mesh.primitives())
+            .map(|primitive| primitive.material())
+            .find(|material| material.index().is_none());
+        for material in gltf.document.materials().chain(default_material) {
+            let baked = material
+                .index()
+                .and_then(|index| baked_materials.get(&index));
+            let (bind_group, info) =
+                self.create_gltf_material(gltf, &material, baked, &mut textures, &mut samplers)?;
             self.material_data
-                .entry(material.index())
-                .or_insert(bind_group);
+                .insert((model_id, material.index()), bind_group);
+            model.materials.insert(material.index(), info);
         }
 
-        let mut primitive_instances: HashMap<_, Vec<_>> = HashMap::new();
+        let mut mesh_nodes: HashMap<_, Vec<_>> = HashMap::new();
         for node in gltf.document.nodes() {
-            let Some(mesh) = node.mesh() else { continue; };
-            let name = node.name().unwrap_or("<Unnamed>");
-            let node_buffer = self
-                .device
-                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                    label: Some(&format!("Node Buffer: {:?}", name)),
-                    contents: bytemuck::bytes_of(&node.transform().matrix()),
-                    usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-                });
-
-            for primitive in mesh.primitives() {
-                let pindex = primitive.index();
-                let bind_group = self.device.create_bind_group(&wgpu::BindGroupDescriptor {
-                    label: Some(&format!("Node Bind Group: {:?} {}", name, pindex)),
-                    layout: &self.node_bind_group_layout,
-                    entries: &[wgpu::BindGroupEntry {
-                        binding: 0,
-                        resource: node_buffer.as_entire_binding(),
-                    }],
-                });
-                primitive_instances
-                    .entry((mesh.index(), pindex))
+            if let Some(mesh) = node.mesh() {
+                mesh_nodes
+                    .entry(mesh.index())
                     .or_default()
-                    .push(bind_group);
+                    .push(node.index());
             }
         }
 
         for mesh in gltf.document.meshes() {
-            let mesh_name = mesh.name().unwrap_or("<Unnamed>");
             for primitive in mesh.primitives() {
-                let reader = primitive.reader(|buffer| Some(&gltf.buffers[buffer.index()]));
-
-                let Some(positions) = reader.read_positions() else { continue; };
-                let normals = reader.read_normals().unwrap_repeat();
-                let tex_coords = reader.read_tex_coords(0).map(|t| t.into_f32());
-                let vertices = zip(zip(positions, normals), tex_coords.unwrap_repeat())
-                    .map(|((position, normal), tex_coord)| MeshVertex {
-                        position,
-                        normal,
-                        tex_coord,
-                    })
-                    .collect::<Vec<_>>();
-                let buffer = self
-                    .device
-                    .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                        label: Some(&format!("Mesh Buffer: {mesh_name}")),
-                        contents: bytemuck::cast_slice(&vertices),
-                        usage: wgpu::BufferUsages::VERTEX,
-                    });
-
-                let material = primitive.material();
-
-                let args = PipelineArgs::new(
-                    mesh_mode_to_topology(primitive.mode()),
-                    self.surface_config.format,
-                    material.double_sided(),
-                    material.alpha_mode(),
-                );
-
-                let draw_mode = match reader.read_indices() {
-                    None => DrawMode::Normal(vertices.len() as _),
-                    Some(indices) => {
-                        let data: Vec<_> = indices.into_u32().collect();
-                        let buffer =
-                            self.device
-                                .create_buffer_init(&wgpu::util::BufferInitDescriptor {
-                                    label: Some(&format!("Index Buffer {mesh_name}")),
-                                    contents: bytemuck::cast_slice(&data),
-                                    usage: wgpu::BufferUsages::INDEX,
-                                });
-                        DrawMode::Indexed {
-                            buffer,
-                            draw_count: data.len() as _,
-                        }
-                    }
+                let Some(PreparedPrimitive { vertices, indices }) =
+                    primitives.get(&(mesh.index(), primitive.index()))
+                else {
+                    continue;
                 };
 
-                let instances = primitive_instances
-                    .remove(&(mesh.index(), primitive.index()))
-                    .context("Invalid GLTF: Visited same primitive twice.")?;
+                let material = primitive.material().index();
+                let topology = mesh_mode_to_topology(primitive.mode());
+                let args = self.pipeline_args(topology, &model.materials[&material]);
 
+                let (vertex_range, draw_mode) =
+                    match self.allocate_geometry(vertices, indices.as_ref()) {
+                        Ok(geometry) => geometry,
+                        Err(err) => {
+                            self.free_model(model_id);
+                            return Err(err);
+                        }
+                    };
+                let source = (mesh.index(), primitive.index());
+                let bounds = primitive
+                    .get(&gltf::Semantic::Positions)
+                    .and_then(|accessor| Bounds::from_accessor(&accessor))
+                    .unwrap_or_else(|| {
+                        Bounds::from_points(vertices.iter().map(|v| v.position.into()))
+                    });
                 let gpu_primitive = Primitive {
-                    instances,
+                    source,
+                    vertices: vertex_range,
+                    bounds,
+                    nodes: mesh_nodes.get(&mesh.index()).cloned().unwrap_or_default(),
+                    instances: 0..0,
+                    center: glam::Vec3::ZERO,
+                    world_bounds: Bounds::EMPTY,
+                    instance_bounds: vec![],
+                    draw_slot: 0,
                     draw_mode,
-                    buffer,
                 };
+                self.insert_primitive(args, (model_id, material), gpu_primitive);
+
+                let materials = scene::variant_materials(&primitive);
+                if !materials.is_empty() {
+                    model.variant_mappings.push(VariantMapping {
+                        source,
+                        topology,
+                        default_material: material,
+                        current_material: material,
+                        materials,
+                    });
+                }
+            }
+        }
+
+        model.scene = Scene::from_prepared(prepared);
+        self.models.insert(model_id, model);
+        self.update_instances();
+        Ok(model_id)
+    }
+
+    pub fn add_loaded_model(&mut self, model: LoadedModel) -> Result<ModelId> {
+        match model {
+            LoadedModel::Gltf(prepared) => self.add_prepared_model(*prepared),
+            LoadedModel::Mesh(mesh) => self.add_mesh_model(mesh),
+        }
+    }
+
+    /// Uploads an OBJ, STL or PLY model, every mesh is drawn once with an identity transform.
+    pub fn add_mesh_model(&mut self, document: MeshDocument) -> Result<ModelId> {
+        let model_id = self.next_model_id();
+        let mut model = Model::default();
+
+        let default_material = document
+            .meshes
+            .iter()
+            .any(|mesh| mesh.material.is_none())
+            .then_some((None, None));
+        let materials = document
+            .materials
+            .iter()
+            .enumerate()
+            .map(|(index, material)| (Some(index), Some(material)))
+            .chain(default_material);
+        for (index, material) in materials {
+            let (bind_group, info) = self.create_mesh_material(material);
+            self.material_data.insert((model_id, index), bind_group);
+            model.materials.insert(index, info);
+        }
+
+        // Every mesh gets a node of its own, see `Scene::from_mesh_document`
+        for (index, mesh) in document.meshes.iter().enumerate() {
+            if mesh.vertices.is_empty() {
+                continue;
+            }
+            let (vertices, draw_mode) =
+                match self.allocate_geometry(&mesh.vertices, mesh.indices.as_ref()) {
+                    Ok(geometry) => geometry,
+                    Err(err) => {
+                        self.free_model(model_id);
+                        return Err(err);
+                    }
+                };
+            let args = self.pipeline_args(mesh.topology, &model.materials[&mesh.material]);
+            let primitive = Primitive {
+                source: (index, 0),
+                vertices,
+                bounds: Bounds::from_points(mesh.vertices.iter().map(|v| v.position.into())),
+                nodes: vec![index],
+                instances: 0..0,
+                center: glam::Vec3::ZERO,
+                world_bounds: Bounds::EMPTY,
+                instance_bounds: vec![],
+                draw_slot: 0,
+                draw_mode,
+            };
+            self.insert_primitive(args, (model_id, mesh.material), primitive);
+        }
 
-                let primitive = self.pipeline_data.entry(args).or_insert_with_key(|args| {
-                    let pipeline = create_mesh_pipeline(&self.device, &self.pipeline_layout, args);
-                    GpuPipeline {
-                        pipeline,
-                        primitives: HashMap::new(),
+        model.scene = Scene::from_mesh_document(document)?;
+        self.models.insert(model_id, model);
+        self.update_instances();
+        Ok(model_id)
+    }
+
+    /// Unloads the model, its geometry and materials are freed.
+    pub fn remove_model(&mut self, model_id: ModelId) -> Result<()> {
+        self.models.remove(&model_id).context("Unknown model")?;
+        self.free_model(model_id);
+        self.update_instances();
+        Ok(())
+    }
+
+    /// Frees the geometry and materials of a model, also of one that failed to upload.
+    fn free_model(&mut self, model_id: ModelId) {
+        for pipeline in self.pipeline_data.values_mut() {
+            pipeline.primitives.retain(|&(id, _), primitives| {
+                if id != model_id {
+                    return true;
+                }
+                for primitive in primitives.drain(..) {
+                    self.geometry.free_vertices(primitive.vertices);
+                    if let DrawMode::Indexed { indices, format } = primitive.draw_mode {
+                        self.geometry.free_indices(indices, format);
                     }
-                });
-                primitive
-                    .primitives
-                    .entry(material.index())
-                    .or_insert(vec![])
-                    .push(gpu_primitive);
+                }
+                false
+            });
+        }
+        self.pipeline_data
+            .retain(|_, pipeline| !pipeline.primitives.is_empty());
+        self.material_data.retain(|&(id, _), _| id != model_id);
+    }
+
+    pub fn model(&self, model: ModelId) -> Option<&Model> {
+        self.models.get(&model)
+    }
+
+    /// Rebinds primitives of the model to the materials of `KHR_materials_variants` variant.
+    /// `None` restores the default materials.
+    pub fn set_material_variant(&mut self, model_id: ModelId, name: Option<&str>) -> Result<()> {
+        let model = self.models.get_mut(&model_id).context("Unknown model")?;
+        let variant = name
+            .map(|name| {
+                model
+                    .variants
+                    .iter()
+                    .position(|variant| variant == name)
+                    .with_context(|| format!("Model has no material variant {name:?}"))
+            })
+            .transpose()?;
+        model.active_variant = variant;
+
+        let mut changed = false;
+        for index in 0..model.variant_mappings.len() {
+            let model = &self.models[&model_id];
+            let mapping = &model.variant_mappings[index];
+            let current_material = mapping.current_material;
+            let material = variant
+                .and_then(|variant| mapping.materials.get(&variant).copied())
+                .or(mapping.default_material);
+            if material == current_material {
+                continue;
             }
+
+            let current_args =
+                self.pipeline_args(mapping.topology, &model.materials[&current_material]);
+            let args = self.pipeline_args(mapping.topology, &model.materials[&material]);
+            let source = mapping.source;
+
+            let primitives = self
+                .pipeline_data
+                .get_mut(&current_args)
+                .and_then(|pipeline| pipeline.primitives.get_mut(&(model_id, current_material)))
+                .context("Variant primitive is missing from its pipeline")?;
+            let position = primitives
+                .iter()
+                .position(|primitive| primitive.source == source)
+                .context("Variant primitive is missing from its pipeline")?;
+            let primitive = primitives.swap_remove(position);
+
+            self.insert_primitive(args, (model_id, material), primitive);
+            let model = self.models.get_mut(&model_id).context("Unknown model")?;
+            model.variant_mappings[index].current_material = material;
+            changed = true;
+        }
+        // Blend primitives draw their instances one by one and need draw slots of their own
+        if changed {
+            self.update_instances();
         }
+        Ok(())
+    }
 
+    /// Sets the transform of the node relative to its parent, its descendants move along.
+    pub fn set_node_transform(
+        &mut self,
+        model_id: ModelId,
+        node: usize,
+        transform: glam::Mat4,
+    ) -> Result<()> {
+        let model = self.models.get_mut(&model_id).context("Unknown model")?;
+        let scene_node = model.scene.nodes.get_mut(node).context("Unknown node")?;
+        scene_node.transform = transform;
+        self.update_instances();
         Ok(())
     }
 
+    /// Draws the mesh of the node once more, `transform` is relative to the node.
+    pub fn add_node_instance(
+        &mut self,
+        model_id: ModelId,
+        node: usize,
+        transform: glam::Mat4,
+    ) -> Result<()> {
+        let model = self.models.get_mut(&model_id).context("Unknown model")?;
+        let scene_node = model.scene.nodes.get_mut(node).context("Unknown node")?;
+        ensure!(
+            scene_node.mesh.is_some(),
+            "Node {node} has no mesh to instance"
+        );
+        if scene_node.instances.is_empty() {
+            scene_node.instances.push(glam::Mat4::IDENTITY);
+        }
+        scene_node.instances.push(transform);
+        self.update_instances();
+        Ok(())
+    }
+
+    /// Packs the world transforms of all primitives into the instance buffer, every
+    /// primitive draws its range with one instanced draw. Also updates the centers the
+    /// render queue sorts by.
+    fn update_instances(&mut self) {
+        let world_transforms: HashMap<_, _> = self
+            .models
+            .iter()
+            .map(|(&id, model)| (id, model.scene.world_transforms()))
+            .collect();
+        let mut transforms = vec![];
+        let mut draws = vec![];
+        let mut draw_ids = vec![];
+        let primitives = self
+            .pipeline_data
+            .values_mut()
+            .flat_map(|pipeline| &mut pipeline.primitives);
+        for ((model_id, material), primitives) in primitives {
+            let model = &self.models[model_id];
+            let nodes = &model.scene.nodes;
+            let world_transforms = &world_transforms[model_id];
+            let is_blend = model.materials[material].alpha_mode == gltf::material::AlphaMode::Blend;
+            for primitive in primitives {
+                let start = transforms.len();
+                for &node in &primitive.nodes {
+                    let world = world_transforms[node];
+                    match nodes[node].instances.as_slice() {
+                        [] => transforms.push(world),
+                        instances => transforms.extend(instances.iter().map(|&t| world * t)),
+                    }
+                }
+                let center = primitive.bounds.center();
+                let sum: glam::Vec3 = transforms[start..]
+                    .iter()
+                    .map(|transform| transform.transform_point3(center))
+                    .sum();
+                primitive.center = sum / (transforms.len() - start).max(1) as f32;
+                let instance_bounds: Vec<_> = transforms[start..]
+                    .iter()
+                    .map(|&transform| primitive.bounds.transform(transform))
+                    .collect();
+                primitive.world_bounds = instance_bounds
+                    .iter()
+                    .copied()
+                    .fold(Bounds::EMPTY, Bounds::union);
+                primitive.draw_slot = draws.len() as u32;
+                primitive.instances = start as u32..transforms.len() as u32;
+                if is_blend {
+                    for instance in primitive.instances.clone() {
+                        draw_ids.push(draws.len() as u32);
+                        draws.push(GpuDraw::new(primitive, instance));
+                    }
+                    primitive.instance_bounds = instance_bounds;
+                } else {
+                    draw_ids.resize(transforms.len(), primitive.draw_slot);
+                    draws.push(GpuDraw::new(primitive, primitive.instances.start));
+                    primitive.instance_bounds = vec![];
+                }
+            }
+        }
+
+        let transforms: Vec<_> = transforms.into_iter().map(InstanceTransform::new).collect();
+        self.instance_binding
+            .update(&self.device, &self.queue, &transforms);
+        if let Some(culling) = &mut self.gpu_culling {
+            culling.update(
+                &self.device,
+                &self.queue,
+                &self.instance_binding,
+                &draws,
+                &draw_ids,
+            );
+        }
+    }
+
+    pub fn culling_mode(&self) -> CullingMode {
+        self.culling
+    }
+
+    /// Switches between culling primitives on the CPU and instances on the GPU, stays on
+    /// the CPU when the adapter doesn't support indirect draws with a first instance.
+    pub fn set_culling_mode(&mut self, mode: CullingMode) {
+        if mode.is_gpu() && self.gpu_culling.is_none() {
+            warn!("GPU culling needs Features::INDIRECT_FIRST_INSTANCE");
+            return;
+        }
+        if self.culling == mode {
+            return;
+        }
+        self.culling = mode;
+        self.cull_stats.set(CullStats::default());
+        // Restores the identity instance indices the culling pass overwrote
+        self.update_instances();
+    }
+
+    /// Visible and culled instances of the last frame on the CPU, or of a recent frame on the
+    /// GPU.
+    pub fn cull_stats(&self) -> CullStats {
+        self.cull_stats.get()
+    }
+
+    pub fn alpha_to_coverage(&self) -> bool {
+        self.alpha_to_coverage
+    }
+
+    /// Switches MASK materials between alpha-to-coverage and a hard cutoff.
+    pub fn set_alpha_to_coverage(&mut self, enabled: bool) {
+        if self.alpha_to_coverage == enabled {
+            return;
+        }
+        self.alpha_to_coverage = enabled;
+        self.respecialize_pipelines(|args| {
+            args.alpha_to_coverage = enabled && args.alpha_mode == gltf::material::AlphaMode::Mask;
+        });
+    }
+
+    pub fn transparency_mode(&self) -> TransparencyMode {
+        self.transparency
+    }
+
+    /// Switches BLEND materials between sorted blending and weighted blended OIT.
+    pub fn set_transparency_mode(&mut self, mode: TransparencyMode) {
+        if self.transparency == mode {
+            return;
+        }
+        self.transparency = mode;
+        self.respecialize_pipelines(|args| {
+            args.weighted_blended_oit = mode == TransparencyMode::WeightedBlended
+                && args.alpha_mode == gltf::material::AlphaMode::Blend;
+        });
+    }
+
+    /// Moves primitives to pipelines with updated args, recreating the ones that changed.
+    fn respecialize_pipelines(&mut self, update: impl Fn(&mut PipelineArgs)) {
+        self.pipeline_data = std::mem::take(&mut self.pipeline_data)
+            .into_iter()
+            .map(|(mut args, pipeline)| {
+                let previous = args.clone();
+                update(&mut args);
+                if args == previous {
+                    return (args, pipeline);
+                }
+                let pipeline = GpuPipeline {
+                    pipeline: create_mesh_pipeline(&self.device, &self.pipeline_layout, &args),
+                    primitives: pipeline.primitives,
+                };
+                (args, pipeline)
+            })
+            .collect();
+    }
+
+    /// Writes the model the way it's drawn to `.gltf` or `.glb`, including the active
+    /// material variant, see [`export`].
+    pub fn export_model(&self, model_id: ModelId, path: impl AsRef<Path>) -> Result<()> {
+        let model = self.models.get(&model_id).context("Unknown model")?;
+        export::export(&model.scene, model.active_variant, path)
+    }
+
+    fn next_model_id(&mut self) -> ModelId {
+        self.next_model_id += 1;
+        ModelId(self.next_model_id - 1)
+    }
+
+    /// Copies the primitive into the geometry arena, nothing stays allocated on failure.
+    fn allocate_geometry(
+        &mut self,
+        vertices: &[MeshVertex],
+        indices: Option<&Indices>,
+    ) -> Result<(Range<u32>, DrawMode)> {
+        let vertex_range = self
+            .geometry
+            .allocate_vertices(&self.device, &self.queue, vertices)?;
+        let Some(indices) = indices else {
+            return Ok((vertex_range, DrawMode::Normal));
+        };
+        match self
+            .geometry
+            .allocate_indices(&self.device, &self.queue, indices)
+        {
+            Ok(index_range) => {
+                let draw_mode = DrawMode::Indexed {
+                    indices: index_range,
+                    format: indices.format(),
+                };
+                Ok((vertex_range, draw_mode))
+            }
+            Err(err) => {
+                self.geometry.free_vertices(vertex_range);
+                Err(err)
+            }
+        }
+    }
+
+    fn pipeline_args(
+        &self,
+        topology: wgpu::PrimitiveTopology,
+        material: &MaterialInfo,
+    ) -> PipelineArgs {
+        PipelineArgs::new(
+            topology,
+            self.surface_config.format,
+            material,
+            self.alpha_to_coverage,
+            self.transparency,
+        )
+    }
+
+    fn insert_primitive(
+        &mut self,
+        args: PipelineArgs,
+        material: MaterialKey,
+        primitive: Primitive,
+    ) {
+        let pipeline = self.pipeline_data.entry(args).or_insert_with_key(|args| {
+            let pipeline = create_mesh_pipeline(&self.device, &self.pipeline_layout, args);
+            GpuPipeline {
+                pipeline,
+                primitives: HashMap::new(),
+            }
+        });
+        pipeline
+            .primitives
+            .entry(material)
+            .or_default()
+            .push(primitive);
+    }
+
+    fn create_gltf_material(
+        &self,
+        gltf: &GltfDocument,
+        material: &gltf::Material,
+        baked: Option<&BakedMaterial>,
+        textures: &mut HashMap<usize, wgpu::TextureView>,
+        samplers: &mut HashMap<Option<usize>, wgpu::Sampler>,
+    ) -> Result<(wgpu::BindGroup, MaterialInfo)> {
+        let pbr = material.pbr_metallic_roughness();
+        let extensions = MaterialExtensions::from_material(material);
+        let mut uniform = MaterialUniform::new(material, &extensions);
+
+        let mut base_color = pbr.base_color_texture().map(|t| t.texture());
+        let mut metallic_roughness = pbr.metallic_roughness_texture().map(|t| t.texture());
+        let mut sampler_texture = base_color.clone();
+        let mut baked_base_color = None;
+        let mut baked_metallic_roughness = None;
+        if let Some(baked) = baked {
+            let [r, g, b, _] = baked.base_color_factor;
+            uniform.base_color_factor = [r, g, b];
+            uniform.metallic_factor = baked.metallic_factor;
+            uniform.roughness_factor = baked.roughness_factor;
+            baked_base_color = baked
+                .base_color_mips
+                .as_ref()
+                .map(|levels| self.create_texture(levels, Some("Baked Base Color")));
+            baked_metallic_roughness = baked
+                .metallic_roughness_mips
+                .as_ref()
+                .map(|levels| self.create_texture(levels, Some("Baked Metallic Roughness")));
+            // Baked textures keep sampling the way the source ones did
+            sampler_texture = baked
+                .texture
+                .and_then(|index| gltf.document.textures().nth(index));
+            base_color = None;
+            metallic_roughness = None;
+            info!(
+                "Converted spec-gloss material {:?} to metallic-roughness",
+                material.name().unwrap_or("<Unnamed>")
+            );
+        }
+
+        let normal = material.normal_texture().map(|t| t.texture());
+        let gltf_textures = base_color
+            .iter()
+            .chain(&metallic_roughness)
+            .chain(&normal)
+            .map(|t| t.index())
+            .chain(extensions.textures());
+        for index in gltf_textures {
+            if let Entry::Vacant(entry) = textures.entry(index) {
+                let texture = gltf
+                    .document
+                    .textures()
+                    .nth(index)
+                    .context("Invalid GLTF: Texture index out of bounds.")?;
+                entry.insert(self.create_gltf_texture(gltf, &texture)?);
+            }
+        }
+        if let Some(texture) = &sampler_texture {
+            let sampler = texture.sampler();
+            samplers
+                .entry(sampler.index())
+                .or_insert_with(|| convert_sampler(&self.device, sampler));
+        }
+
+        let view = |texture: Option<usize>| texture.map(|index| &textures[&index]);
+        let sampler = sampler_texture
+            .as_ref()
+            .map_or(&self.default_sampler, |t| &samplers[&t.sampler().index()]);
+        let specular = extensions.specular;
+        let iridescence = extensions.iridescence;
+        let anisotropy = extensions.anisotropy;
+        let views = MaterialViews {
+            base_color: baked_base_color
+                .as_ref()
+                .or_else(|| view(base_color.as_ref().map(|t| t.index()))),
+            metallic_roughness: baked_metallic_roughness
+                .as_ref()
+                .or_else(|| view(metallic_roughness.as_ref().map(|t| t.index()))),
+            normal: view(normal.as_ref().map(|t| t.index())),
+            specular: view(specular.and_then(|s| s.texture)),
+            specular_color: view(specular.and_then(|s| s.color_texture)),
+            iridescence: view(iridescence.and_then(|i| i.texture)),
+            iridescence_thickness: view(iridescence.and_then(|i| i.thickness_texture)),
+            anisotropy: view(anisotropy.and_then(|a| a.texture)),
+        };
+        // Baked spec-gloss textures replace the ones of the document
+        let mut info = MaterialInfo::new(material);
+        info.features.base_color_texture = views.base_color.is_some();
+        info.features.metallic_roughness_texture = views.metallic_roughness.is_some();
+        let label = format!("{:?}", material.index());
+        let bind_group = self.create_material_bind_group(&label, &uniform, views, sampler);
+        Ok((bind_group, info))
+    }
+
+    fn create_material_bind_group(
+        &self,
+        label: &str,
+        uniform: &MaterialUniform,
+        views: MaterialViews,
+        sampler: &wgpu::Sampler,
+    ) -> wgpu::BindGroup {
+        let buffer = self
+            .device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: Some(&format!("Material Uniform: {label}")),
+                contents: bytemuck::bytes_of(uniform),
+                usage: wgpu::BufferUsages::UNIFORM,
+            });
+
+        let white_texture_view = self.opaque_white_texture.create_view(&Default::default());
+        let view = |view: Option<_>| {
+            wgpu::BindingResource::TextureView(view.unwrap_or(&white_texture_view))
+        };
+
+        use material::binding;
+        self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some(&format!("Material Bind Group: {label}")),
+            layout: &self.material_bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: binding::UNIFORM,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: binding::BASE_COLOR_TEXTURE,
+                    // TODO: Stick to always sRGB format... or not
+                    resource: view(views.base_color),
+                },
+                wgpu::BindGroupEntry {
+                    binding: binding::SAMPLER,
+                    resource: wgpu::BindingResource::Sampler(sampler),
+                },
+                wgpu::BindGroupEntry {
+                    binding: binding::SPECULAR_TEXTURE,
+                    resource: view(views.specular),
+                },
+                wgpu::BindGroupEntry {
+                    binding: binding::SPECULAR_COLOR_TEXTURE,
+                    resource: view(views.specular_color),
+                },
+                wgpu::BindGroupEntry {
+                    binding: binding::IRIDESCENCE_TEXTURE,
+                    resource: view(views.iridescence),
+                },
+                wgpu::BindGroupEntry {
+                    binding: binding::IRIDESCENCE_THICKNESS_TEXTURE,
+                    resource: view(views.iridescence_thickness),
+                },
+                wgpu::BindGroupEntry {
+                    binding: binding::ANISOTROPY_TEXTURE,
+                    resource: view(views.anisotropy),
+                },
+                wgpu::BindGroupEntry {
+                    binding: binding::METALLIC_ROUGHNESS_TEXTURE,
+                    resource: view(views.metallic_roughness),
+                },
+                wgpu::BindGroupEntry {
+                    binding: binding::NORMAL_TEXTURE,
+                    resource: view(views.normal),
+                },
+            ],
+        })
+    }
+
+    /// `None` is the default material of meshes without one.
+    fn create_mesh_material(
+        &self,
+        material: Option<&MeshMaterial>,
+    ) -> (wgpu::BindGroup, MaterialInfo) {
+        let Some(material) = material else {
+            let uniform = MaterialUniform {
+                metallic_factor: 0.,
+                ..Default::default()
+            };
+            let bind_group = self.create_material_bind_group(
+                "Mesh Default",
+                &uniform,
+                MaterialViews::default(),
+                &self.default_sampler,
+            );
+            return (bind_group, MaterialInfo::default());
+        };
+
+        let uniform = MaterialUniform {
+            base_color_factor: material.base_color_factor,
+            metallic_factor: 0.,
+            roughness_factor: material.roughness_factor,
+            ..Default::default()
+        };
+        let base_color = material
+            .base_color_mips
+            .as_ref()
+            .map(|levels| self.create_texture(levels, Some(&material.name)));
+        let normal = material
+            .normal_mips
+            .as_ref()
+            .map(|levels| self.create_texture(levels, Some(&material.name)));
+        let views = MaterialViews {
+            base_color: base_color.as_ref(),
+            normal: normal.as_ref(),
+            ..Default::default()
+        };
+        let bind_group =
+            self.create_material_bind_group(&material.name, &uniform, views, &self.default_sampler);
+        let info = MaterialInfo {
+            double_sided: false,
+            alpha_mode: material.alpha_mode,
+            features: MaterialFeatures {
+                base_color_texture: base_color.is_some(),
+                normal_map: normal.is_some(),
+                ..Default::default()
+            },
+        };
+        (bind_group, info)
+    }
+
+    /// Uploads the first image source of the texture the adapter can use.
+    fn create_gltf_texture(
+        &self,
+        gltf: &GltfDocument,
+        texture: &gltf::Texture,
+    ) -> Result<wgpu::TextureView> {
+        let mut error = None;
+        for source in texture_sources(texture) {
+            let view = match gltf.images.get(source) {
+                Some(Image::Rgba(levels)) => Ok(self.create_texture(levels, texture.name())),
+                Some(Image::Ktx2(ktx2)) => self.create_ktx2_texture(ktx2, texture.name()),
+                Some(Image::Unsupported(err)) => Err(eyre!("Failed to decode image: {err}")),
+                None => Err(eyre!("Invalid GLTF: Image index {source} out of bounds.")),
+            };
+            match view {
+                Ok(view) => return Ok(view),
+                Err(err) => {
+                    warn!(
+                        "Skipping image {source} of texture {:?}: {err}",
+                        texture.index()
+                    );
+                    error = Some(err);
+                }
+            }
+        }
+        Err(error.unwrap_or_else(|| eyre!("Texture {} has no image.", texture.index())))
+    }
+
+    /// Uploads pre-built mip levels of a KTX2 texture, Basis Universal payloads are
+    /// transcoded first.
+    fn create_ktx2_texture(&self, ktx2: &Ktx2, label: Option<&str>) -> Result<wgpu::TextureView> {
+        let (format, levels) = ktx2.texture_data(self.features)?;
+        let info = format.describe();
+        if !self.features.contains(info.required_features) {
+            bail!("Adapter doesn't support {format:?} textures.");
+        }
+        let (block_width, block_height) = info.block_dimensions;
+        if !ktx2.width.is_multiple_of(block_width as u32)
+            || !ktx2.height.is_multiple_of(block_height as u32)
+        {
+            bail!(
+                "{}x{} texture is not a multiple of {format:?} block size.",
+                ktx2.width,
+                ktx2.height
+            );
+        }
+
+        let texture = self.device.create_texture_with_data(
+            &self.queue,
+            &wgpu::TextureDescriptor {
+                label,
+                size: wgpu::Extent3d {
+                    width: ktx2.width,
+                    height: ktx2.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: levels.len() as _,
+                sample_count: 1,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+                view_formats: &[],
+            },
+            &levels.concat(),
+        );
+        Ok(texture.create_view(&Default::default()))
+    }
+
+    /// Uploads the given mip levels, missing ones are generated on the GPU.
+    fn create_texture(
+        &self,
+        levels: &[image::RgbaImage],
+        label: Option<&str>,
+    ) -> wgpu::TextureView {
+        let (width, height) = levels[0].dimensions();
+        let mip_level_count = width.max(height).ilog2() + 1;
+        let size = wgpu::Extent3d {
+            width,
+            height,
+            depth_or_array_layers: 1,
+        };
+
+        let desc = wgpu::TextureDescriptor {
+            label,
+            size,
+            mip_level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: wgpu::TextureFormat::Rgba8Unorm,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING
+                | wgpu::TextureUsages::COPY_DST
+                | wgpu::TextureUsages::RENDER_ATTACHMENT,
+
+            view_formats: &[],
+        };
+        let texture = self.device.create_texture(&desc);
+        for (mip_level, image) in levels.iter().enumerate() {
+            let (width, height) = image.dimensions();
+            self.queue.write_texture(
+                wgpu::ImageCopyTextureBase {
+                    texture: &texture,
+                    mip_level: mip_level as _,
+                    origin: wgpu::Origin3d::ZERO,
+                    aspect: wgpu::TextureAspect::All,
+                },
+                image.as_raw(),
+                wgpu::ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(width * 4),
+                    rows_per_image: None,
+                },
+                wgpu::Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+
+        if levels.len() < mip_level_count as usize {
+            let mut encoder = self.device.create_command_encoder(&Default::default());
+            self.blitter
+                .generate_mipmaps(&self.device, &mut encoder, &texture);
+            self.queue.submit(Some(encoder.finish()));
+        }
+
+        texture.create_view(&Default::default())
+    }
+
     fn create_depth_texture(
         device: &wgpu::Device,
         config: &wgpu::SurfaceConfiguration,
@@ -790,3 +1715,29 @@ impl Display for RendererInfo {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use gltf::material::AlphaMode;
+
+    use super::*;
+
+    #[test]
+    fn blend_occluders_keep_hiz_depth() {
+        let args = |alpha_mode, transparency| {
+            let material = MaterialInfo {
+                alpha_mode,
+                ..Default::default()
+            };
+            let format = wgpu::TextureFormat::Rgba8Unorm;
+            let topology = wgpu::PrimitiveTopology::TriangleList;
+            PipelineArgs::new(topology, format, &material, true, transparency)
+        };
+        // Opaque instances behind a blended occluder stay visible to occlusion culling
+        for transparency in [TransparencyMode::Sorted, TransparencyMode::WeightedBlended] {
+            assert!(!args(AlphaMode::Blend, transparency).depth_write());
+            assert!(args(AlphaMode::Opaque, transparency).depth_write());
+            assert!(args(AlphaMode::Mask, transparency).depth_write());
+        }
+    }
+}
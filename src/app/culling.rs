@@ -0,0 +1,98 @@
+//! View frustum culling of primitives by their world-space bounds.
+
+use glam::{Mat4, Vec4};
+
+use super::Bounds;
+
+/// Where primitives outside the view frustum are skipped.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum CullingMode {
+    /// Whole primitives by the bounds of all their instances, drawn directly
+    #[default]
+    Cpu,
+    /// Single instances in a compute pass, drawn indirectly
+    Gpu,
+    /// Like [`CullingMode::Gpu`], instances behind the depth of the last frame are culled as
+    /// well
+    GpuOcclusion,
+}
+
+impl CullingMode {
+    /// `WGLTF_CULLING=gpu` selects [`CullingMode::Gpu`], `WGLTF_CULLING=gpu-occlusion`
+    /// [`CullingMode::GpuOcclusion`].
+    pub fn from_env() -> Self {
+        match std::env::var("WGLTF_CULLING").as_deref() {
+            Ok("gpu") => Self::Gpu,
+            Ok("gpu-occlusion") => Self::GpuOcclusion,
+            _ => Self::Cpu,
+        }
+    }
+
+    pub fn is_gpu(self) -> bool {
+        matches!(self, Self::Gpu | Self::GpuOcclusion)
+    }
+}
+
+/// Planes of the view frustum with normals pointing inside.
+#[derive(Debug, Clone, Copy)]
+pub struct Frustum {
+    planes: [Vec4; 5],
+}
+
+impl Frustum {
+    /// Planes of an infinite reverse-Z projection. The near plane is at `z = w` and depth
+    /// only reaches 0 at infinity, so there is no far plane.
+    pub fn from_projection_view(proj_view: Mat4) -> Self {
+        let [x, y, z, w] = [0, 1, 2, 3].map(|index| proj_view.row(index));
+        let planes =
+            [w + x, w - x, w + y, w - y, w - z].map(|plane| plane / plane.truncate().length());
+        Self { planes }
+    }
+
+    pub fn planes(&self) -> [Vec4; 5] {
+        self.planes
+    }
+
+    /// Conservative, boxes outside near a frustum corner still count as intersecting.
+    pub fn intersects(&self, bounds: &Bounds) -> bool {
+        let center = bounds.center();
+        let extents = (bounds.max - bounds.min) / 2.;
+        self.planes.iter().all(|plane| {
+            let normal = plane.truncate();
+            normal.dot(center) + normal.abs().dot(extents) + plane.w >= 0.
+        })
+    }
+}
+
+/// Instances of the last frame culled on the CPU, or of a recent frame culled on the GPU.
+/// The CPU culls all instances of a draw together.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct CullStats {
+    pub visible: usize,
+    pub culled: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{vec3, Vec3};
+
+    use super::*;
+
+    #[test]
+    fn reverse_z_frustum() {
+        let proj = Mat4::perspective_infinite_reverse_rh(std::f32::consts::FRAC_PI_2, 1., 0.1);
+        let view = Mat4::look_at_rh(Vec3::ZERO, Vec3::NEG_Z, Vec3::Y);
+        let frustum = Frustum::from_projection_view(proj * view);
+        let cube = |center: Vec3| Bounds {
+            min: center - 0.5,
+            max: center + 0.5,
+        };
+
+        assert!(frustum.intersects(&cube(vec3(0., 0., -5.))));
+        assert!(frustum.intersects(&cube(vec3(0., 0., -1e6))));
+        assert!(frustum.intersects(&cube(vec3(5., 0., -5.))));
+        assert!(!frustum.intersects(&cube(vec3(0., 0., 5.))));
+        assert!(!frustum.intersects(&cube(vec3(7., 0., -5.))));
+        assert!(!frustum.intersects(&cube(vec3(0., -7., -5.))));
+    }
+}
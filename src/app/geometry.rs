@@ -0,0 +1,339 @@
+//! Vertices and indices of all primitives, sub-allocated from two shared buffers.
+
+use std::{marker::PhantomData, ops::Range};
+
+use bytemuck::Pod;
+use color_eyre::{eyre::ContextCompat, Result};
+
+use super::MeshVertex;
+
+/// Index data at the width of the source, wgpu has no 8-bit indices so those become 16-bit.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Indices {
+    U16(Vec<u16>),
+    U32(Vec<u32>),
+}
+
+impl Indices {
+    pub fn format(&self) -> wgpu::IndexFormat {
+        match self {
+            Self::U16(_) => wgpu::IndexFormat::Uint16,
+            Self::U32(_) => wgpu::IndexFormat::Uint32,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        match self {
+            Self::U16(indices) => indices.len(),
+            Self::U32(indices) => indices.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            Self::U16(indices) => bytemuck::cast_slice(indices),
+            Self::U32(indices) => bytemuck::cast_slice(indices),
+        }
+    }
+}
+
+/// Initial arena sizes in elements, they double whenever an allocation doesn't fit up to
+/// `Limits::max_buffer_size`.
+const VERTEX_CAPACITY: u32 = 1 << 16;
+const INDEX_CAPACITY: u32 = 1 << 18;
+
+/// First fit allocator of element ranges, freed ranges merge with their neighbors.
+#[derive(Debug)]
+struct RangeAllocator {
+    /// Sorted by start and never adjacent
+    free: Vec<Range<u32>>,
+    size: u32,
+}
+
+impl RangeAllocator {
+    fn new(size: u32) -> Self {
+        let mut allocator = Self {
+            free: vec![],
+            size: 0,
+        };
+        allocator.grow(size);
+        allocator
+    }
+
+    fn allocate(&mut self, len: u32) -> Option<Range<u32>> {
+        let index = self
+            .free
+            .iter()
+            .position(|range| range.len() >= len as usize)?;
+        let start = self.free[index].start;
+        self.free[index].start += len;
+        if self.free[index].is_empty() {
+            self.free.remove(index);
+        }
+        Some(start..start + len)
+    }
+
+    fn free(&mut self, range: Range<u32>) {
+        if range.is_empty() {
+            return;
+        }
+        let index = self.free.partition_point(|free| free.start < range.start);
+        let merges_prev = index > 0 && self.free[index - 1].end == range.start;
+        let merges_next = self
+            .free
+            .get(index)
+            .is_some_and(|next| next.start == range.end);
+        match (merges_prev, merges_next) {
+            (true, true) => {
+                self.free[index - 1].end = self.free[index].end;
+                self.free.remove(index);
+            }
+            (true, false) => self.free[index - 1].end = range.end,
+            (false, true) => self.free[index].start = range.start,
+            (false, false) => self.free.insert(index, range),
+        }
+    }
+
+    fn grow(&mut self, size: u32) {
+        let old_size = std::mem::replace(&mut self.size, size);
+        self.free(old_size..size);
+    }
+}
+
+/// Next power of two capacity that fits `len` more elements, at most `max`.
+fn grown_capacity(size: u32, len: u32, max: u32) -> u32 {
+    (size as u64 + len as u64)
+        .next_power_of_two()
+        .min(max as u64) as u32
+}
+
+/// Growable GPU buffer of `T` handing out element ranges.
+struct Arena<T> {
+    label: &'static str,
+    usage: wgpu::BufferUsages,
+    buffer: wgpu::Buffer,
+    allocator: RangeAllocator,
+    /// Elements in the largest buffer the device allows
+    max_capacity: u32,
+    _element: PhantomData<T>,
+}
+
+impl<T: Pod> Arena<T> {
+    /// Allocations are padded to whole multiples of this many elements so buffer writes
+    /// stay 4-byte aligned.
+    const ALIGNMENT: u32 =
+        (wgpu::COPY_BUFFER_ALIGNMENT as usize).div_ceil(std::mem::size_of::<T>()) as u32;
+
+    fn new(
+        device: &wgpu::Device,
+        label: &'static str,
+        usage: wgpu::BufferUsages,
+        capacity: u32,
+    ) -> Self {
+        let usage = usage | wgpu::BufferUsages::COPY_DST | wgpu::BufferUsages::COPY_SRC;
+        let max_elements = device.limits().max_buffer_size / std::mem::size_of::<T>() as u64;
+        let max_capacity =
+            max_elements.min(u32::MAX as u64) as u32 / Self::ALIGNMENT * Self::ALIGNMENT;
+        let capacity = capacity.min(max_capacity);
+        Self {
+            label,
+            usage,
+            buffer: Self::create_buffer(device, label, usage, capacity),
+            allocator: RangeAllocator::new(capacity),
+            max_capacity,
+            _element: PhantomData,
+        }
+    }
+
+    /// Fails when the data doesn't fit even after growing to the largest buffer the device
+    /// allows.
+    fn allocate(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        data: &[T],
+    ) -> Result<Range<u32>> {
+        let len = data.len() as u32;
+        if len == 0 {
+            return Ok(0..0);
+        }
+        let padded_len = len.next_multiple_of(Self::ALIGNMENT);
+        let range = match self.allocator.allocate(padded_len) {
+            Some(range) => range,
+            None => {
+                let size = self.allocator.size;
+                let capacity = grown_capacity(size, padded_len, self.max_capacity);
+                if capacity > size {
+                    self.grow(device, queue, capacity);
+                }
+                self.allocator.allocate(padded_len).with_context(|| {
+                    let max_size = device.limits().max_buffer_size;
+                    format!(
+                        "{} is full, buffers are limited to {max_size} bytes.",
+                        self.label
+                    )
+                })?
+            }
+        };
+        let offset = (range.start as usize * std::mem::size_of::<T>()) as u64;
+        if padded_len == len {
+            queue.write_buffer(&self.buffer, offset, bytemuck::cast_slice(data));
+        } else {
+            let mut padded = data.to_vec();
+            padded.resize(padded_len as usize, T::zeroed());
+            queue.write_buffer(&self.buffer, offset, bytemuck::cast_slice(&padded));
+        }
+        Ok(range.start..range.start + len)
+    }
+
+    fn free(&mut self, range: Range<u32>) {
+        let end = range.start + range.len() as u32;
+        self.allocator
+            .free(range.start..end.next_multiple_of(Self::ALIGNMENT));
+    }
+
+    /// Moves the contents to a bigger buffer, ranges handed out stay valid.
+    fn grow(&mut self, device: &wgpu::Device, queue: &wgpu::Queue, capacity: u32) {
+        let buffer = Self::create_buffer(device, self.label, self.usage, capacity);
+        let mut encoder = device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
+            label: Some("Arena Grow Encoder"),
+        });
+        encoder.copy_buffer_to_buffer(&self.buffer, 0, &buffer, 0, self.buffer.size());
+        queue.submit(Some(encoder.finish()));
+        self.buffer = buffer;
+        self.allocator.grow(capacity);
+    }
+
+    fn create_buffer(
+        device: &wgpu::Device,
+        label: &str,
+        usage: wgpu::BufferUsages,
+        capacity: u32,
+    ) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some(label),
+            size: capacity as u64 * std::mem::size_of::<T>() as u64,
+            usage,
+            mapped_at_creation: false,
+        })
+    }
+}
+
+/// Shared vertex and index buffers, bound once per pass. Primitives draw their ranges
+/// with `base_vertex` and `first_index`.
+pub struct GeometryArena {
+    vertices: Arena<MeshVertex>,
+    indices_u16: Arena<u16>,
+    indices_u32: Arena<u32>,
+}
+
+impl GeometryArena {
+    pub fn new(device: &wgpu::Device) -> Self {
+        Self {
+            vertices: Arena::new(
+                device,
+                "Vertex Arena",
+                wgpu::BufferUsages::VERTEX,
+                VERTEX_CAPACITY,
+            ),
+            indices_u16: Arena::new(
+                device,
+                "Index Arena 16-bit",
+                wgpu::BufferUsages::INDEX,
+                INDEX_CAPACITY,
+            ),
+            indices_u32: Arena::new(
+                device,
+                "Index Arena 32-bit",
+                wgpu::BufferUsages::INDEX,
+                INDEX_CAPACITY,
+            ),
+        }
+    }
+
+    pub fn allocate_vertices(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        vertices: &[MeshVertex],
+    ) -> Result<Range<u32>> {
+        self.vertices.allocate(device, queue, vertices)
+    }
+
+    /// Goes to the arena of the index width, see [`GeometryArena::bind_indices`].
+    pub fn allocate_indices(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        indices: &Indices,
+    ) -> Result<Range<u32>> {
+        match indices {
+            Indices::U16(indices) => self.indices_u16.allocate(device, queue, indices),
+            Indices::U32(indices) => self.indices_u32.allocate(device, queue, indices),
+        }
+    }
+
+    pub fn free_vertices(&mut self, range: Range<u32>) {
+        self.vertices.free(range);
+    }
+
+    pub fn free_indices(&mut self, range: Range<u32>, format: wgpu::IndexFormat) {
+        match format {
+            wgpu::IndexFormat::Uint16 => self.indices_u16.free(range),
+            wgpu::IndexFormat::Uint32 => self.indices_u32.free(range),
+        }
+    }
+
+    pub fn bind_vertices<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_vertex_buffer(0, self.vertices.buffer.slice(..));
+    }
+
+    pub fn bind_indices<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>, format: wgpu::IndexFormat) {
+        let buffer = match format {
+            wgpu::IndexFormat::Uint16 => &self.indices_u16.buffer,
+            wgpu::IndexFormat::Uint32 => &self.indices_u32.buffer,
+        };
+        pass.set_index_buffer(buffer.slice(..), format);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{grown_capacity, RangeAllocator};
+
+    #[test]
+    fn allocate_and_free() {
+        let mut allocator = RangeAllocator::new(10);
+        let a = allocator.allocate(3).unwrap();
+        let b = allocator.allocate(3).unwrap();
+        let c = allocator.allocate(3).unwrap();
+        assert_eq!((a.clone(), b.clone(), c.clone()), (0..3, 3..6, 6..9));
+        assert_eq!(allocator.allocate(2), None);
+
+        allocator.free(a);
+        allocator.free(c);
+        assert_eq!(allocator.free, vec![0..3, 6..10]);
+        assert_eq!(allocator.allocate(4), Some(6..10));
+
+        allocator.free(b);
+        assert_eq!(allocator.free, vec![0..6]);
+        allocator.free(6..10);
+        assert_eq!(allocator.free, vec![0..10]);
+
+        allocator.grow(16);
+        assert_eq!(allocator.allocate(16), Some(0..16));
+        assert!(allocator.free.is_empty());
+    }
+
+    #[test]
+    fn capacity_stops_at_limit() {
+        assert_eq!(grown_capacity(1 << 16, 10, u32::MAX), 1 << 17);
+        assert_eq!(grown_capacity(100, 200, 256), 256);
+        assert_eq!(grown_capacity(200, 100, 256), 256);
+        assert_eq!(grown_capacity(3 << 30, 1 << 30, u32::MAX), u32::MAX);
+    }
+}
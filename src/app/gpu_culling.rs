@@ -0,0 +1,487 @@
+//! Frustum and optionally occlusion culling of instances in a compute pass. Visible
+//! instances are compacted into the instance indices and draws read their instance counts
+//! from the indirect arguments.
+
+use std::{
+    borrow::Cow,
+    cell::Cell,
+    mem::{offset_of, size_of},
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+};
+
+use bytemuck::{Pod, Zeroable};
+use glam::Mat4;
+
+use super::{
+    culling::{CullStats, Frustum},
+    hiz::HiZ,
+    instances::InstanceBinding,
+    DrawMode, Primitive,
+};
+
+/// Size of the arguments of one draw, `DrawIndexedIndirect` and the smaller `DrawIndirect`
+/// both fit.
+const ARGS_SIZE: u64 = 5 * size_of::<u32>() as u64;
+const WORKGROUP_SIZE: u32 = 64;
+
+/// Draw of a [`Primitive`] or one of its instances before culling, laid out like `Draw` in
+/// the shader.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct GpuDraw {
+    min: [f32; 3],
+    /// Index or vertex count
+    count: u32,
+    max: [f32; 3],
+    /// First index or first vertex
+    first: u32,
+    base_vertex: i32,
+    first_instance: u32,
+    indexed: u32,
+    padding: u32,
+}
+
+impl GpuDraw {
+    pub fn new(primitive: &Primitive, first_instance: u32) -> Self {
+        let (count, first, indexed) = match &primitive.draw_mode {
+            DrawMode::Normal => (primitive.vertices.len(), primitive.vertices.start, false),
+            DrawMode::Indexed { indices, .. } => (indices.len(), indices.start, true),
+        };
+        Self {
+            min: primitive.bounds.min.into(),
+            count: count as u32,
+            max: primitive.bounds.max.into(),
+            first,
+            base_vertex: primitive.vertices.start as i32,
+            first_instance,
+            indexed: indexed as u32,
+            padding: 0,
+        }
+    }
+}
+
+/// Laid out like `Culling` in the shader.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+struct Uniform {
+    planes: [[f32; 4]; 5],
+    occlusion_view_proj: [[f32; 4]; 4],
+    draw_count: u32,
+    instance_count: u32,
+    hiz_levels: u32,
+    padding: u32,
+}
+
+// Sizes and offsets of `Draw` and `Culling` in cull_instances.wgsl
+const _: () = assert!(size_of::<GpuDraw>() == 48);
+const _: () = assert!(offset_of!(GpuDraw, max) == 16);
+const _: () = assert!(offset_of!(GpuDraw, base_vertex) == 32);
+const _: () = assert!(size_of::<Uniform>() == 160);
+const _: () = assert!(offset_of!(Uniform, occlusion_view_proj) == 80);
+const _: () = assert!(offset_of!(Uniform, draw_count) == 144);
+
+/// Where the copy of the visible instance count is on its way back to the CPU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Readback {
+    Idle,
+    Copied,
+    Mapping,
+}
+
+pub struct GpuCulling {
+    layout: wgpu::BindGroupLayout,
+    reset_pipeline: wgpu::ComputePipeline,
+    cull_pipeline: wgpu::ComputePipeline,
+    uniform: wgpu::Buffer,
+    draws: wgpu::Buffer,
+    draw_ids: wgpu::Buffer,
+    args: wgpu::Buffer,
+    bind_group: wgpu::BindGroup,
+    draw_count: u32,
+    instance_count: u32,
+    multi_draw: bool,
+    hiz: HiZ,
+    /// Projection and view the Hi-Z pyramid was built with, `None` until it is
+    occlusion_view_proj: Cell<Option<Mat4>>,
+    /// Instances that passed culling, counted by the shader
+    visible_count: wgpu::Buffer,
+    stats_readback: wgpu::Buffer,
+    readback: Cell<Readback>,
+    /// Set once `stats_readback` is mapped
+    mapped: Arc<AtomicBool>,
+    /// Instance count of the frame the readback was copied in
+    readback_instances: Cell<u32>,
+}
+
+impl GpuCulling {
+    /// Indirect draws need `first_instance`, adapters without
+    /// `Features::INDIRECT_FIRST_INSTANCE` get `None`. `depth` is the depth buffer of the
+    /// main pass, occlusion is tested against it.
+    pub fn new(
+        device: &wgpu::Device,
+        instances: &InstanceBinding,
+        depth: &wgpu::TextureView,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Option<Self> {
+        let features = device.features();
+        if !features.contains(wgpu::Features::INDIRECT_FIRST_INSTANCE) {
+            return None;
+        }
+
+        let storage_entry = |binding, read_only| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::Buffer {
+                ty: wgpu::BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Culling Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(1, true),
+                storage_entry(2, false),
+                storage_entry(3, true),
+                storage_entry(4, true),
+                storage_entry(5, false),
+                storage_entry(6, false),
+                wgpu::BindGroupLayoutEntry {
+                    binding: 7,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Culling Pipeline Layout"),
+            bind_group_layouts: &[&layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Culling Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/shaders/cull_instances.wgsl"
+            )))),
+        });
+        let create_pipeline = |label, entry_point| {
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point,
+            })
+        };
+
+        let uniform = create_buffer(
+            device,
+            "Culling Uniform Buffer",
+            wgpu::BufferUsages::UNIFORM,
+            size_of::<Uniform>() as u64,
+        );
+        let draws = create_buffer(device, "Draw Buffer", wgpu::BufferUsages::STORAGE, 0);
+        let draw_ids = create_buffer(device, "Draw Id Buffer", wgpu::BufferUsages::STORAGE, 0);
+        let args = create_buffer(
+            device,
+            "Indirect Args Buffer",
+            wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::INDIRECT,
+            0,
+        );
+        let visible_count = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Visible Count Buffer"),
+            size: size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::STORAGE
+                | wgpu::BufferUsages::COPY_SRC
+                | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let stats_readback = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Culling Stats Readback Buffer"),
+            size: size_of::<u32>() as u64,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        let hiz = HiZ::new(device, depth, config);
+        let bind_group = Self::create_bind_group(
+            device,
+            &layout,
+            instances,
+            [&uniform, &draws, &draw_ids, &args, &visible_count],
+            &hiz,
+        );
+        Some(Self {
+            reset_pipeline: create_pipeline("Culling Reset Pipeline", "reset"),
+            cull_pipeline: create_pipeline("Culling Pipeline", "cull"),
+            layout,
+            uniform,
+            draws,
+            draw_ids,
+            args,
+            bind_group,
+            draw_count: 0,
+            instance_count: 0,
+            multi_draw: features.contains(wgpu::Features::MULTI_DRAW_INDIRECT),
+            hiz,
+            occlusion_view_proj: Cell::new(None),
+            visible_count,
+            stats_readback,
+            readback: Cell::new(Readback::Idle),
+            mapped: Arc::new(AtomicBool::new(false)),
+            readback_instances: Cell::new(0),
+        })
+    }
+
+    /// Follows the depth buffer to its new size, occlusion is skipped until the pyramid is
+    /// built again.
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        instances: &InstanceBinding,
+        depth: &wgpu::TextureView,
+        config: &wgpu::SurfaceConfiguration,
+    ) {
+        self.hiz.resize(device, depth, config);
+        self.occlusion_view_proj.set(None);
+        self.bind_group = self.bind_group(device, instances);
+    }
+
+    /// Uploads one draw per primitive and the draw of every instance, buffers grow when
+    /// they don't fit.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        instances: &InstanceBinding,
+        draws: &[GpuDraw],
+        draw_ids: &[u32],
+    ) {
+        let draws_size = std::mem::size_of_val(draws) as u64;
+        grow(device, &mut self.draws, "Draw Buffer", draws_size);
+        let args_size = draws.len() as u64 * ARGS_SIZE;
+        grow(device, &mut self.args, "Indirect Args Buffer", args_size);
+        let draw_ids_size = std::mem::size_of_val(draw_ids) as u64;
+        grow(device, &mut self.draw_ids, "Draw Id Buffer", draw_ids_size);
+        // The instance buffers may have been recreated as well
+        self.bind_group = self.bind_group(device, instances);
+        self.draw_count = draws.len() as u32;
+        self.instance_count = draw_ids.len() as u32;
+        queue.write_buffer(&self.draws, 0, bytemuck::cast_slice(draws));
+        queue.write_buffer(&self.draw_ids, 0, bytemuck::cast_slice(draw_ids));
+    }
+
+    /// Resets the instance counts of all draws and appends the instances inside the frustum.
+    /// With `occlusion`, instances behind the depth of the last frame built with
+    /// [`GpuCulling::build_hiz`] are culled as well, they may appear a frame late.
+    pub fn cull<'a>(
+        &'a self,
+        queue: &wgpu::Queue,
+        pass: &mut wgpu::ComputePass<'a>,
+        frustum: &Frustum,
+        occlusion: bool,
+    ) {
+        if !occlusion {
+            self.occlusion_view_proj.set(None);
+        }
+        let occlusion_view_proj = self.occlusion_view_proj.get();
+        let uniform = Uniform {
+            planes: frustum.planes().map(Into::into),
+            occlusion_view_proj: occlusion_view_proj.unwrap_or_default().to_cols_array_2d(),
+            draw_count: self.draw_count,
+            instance_count: self.instance_count,
+            hiz_levels: occlusion_view_proj.map_or(0, |_| self.hiz.levels()),
+            padding: 0,
+        };
+        queue.write_buffer(&self.uniform, 0, bytemuck::bytes_of(&uniform));
+        queue.write_buffer(&self.visible_count, 0, bytemuck::bytes_of(&0u32));
+        pass.set_bind_group(0, &self.bind_group, &[]);
+        pass.set_pipeline(&self.reset_pipeline);
+        pass.dispatch_workgroups(self.draw_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+        pass.set_pipeline(&self.cull_pipeline);
+        pass.dispatch_workgroups(self.instance_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+
+    /// Builds the pyramid tested by the next frame's [`GpuCulling::cull`] from the depth
+    /// buffer rendered with `view_proj`.
+    pub fn build_hiz<'a>(&'a self, pass: &mut wgpu::ComputePass<'a>, view_proj: Mat4) {
+        self.hiz.build(pass);
+        self.occlusion_view_proj.set(Some(view_proj));
+    }
+
+    /// Copies the visible count of this frame for [`GpuCulling::read_stats`], unless an
+    /// earlier copy is still on its way.
+    pub fn copy_stats(&self, encoder: &mut wgpu::CommandEncoder) {
+        if self.readback.get() != Readback::Idle {
+            return;
+        }
+        encoder.copy_buffer_to_buffer(
+            &self.visible_count,
+            0,
+            &self.stats_readback,
+            0,
+            size_of::<u32>() as u64,
+        );
+        self.readback.set(Readback::Copied);
+        self.readback_instances.set(self.instance_count);
+    }
+
+    /// Maps the copy once the encoder with [`GpuCulling::copy_stats`] was submitted.
+    pub fn map_stats(&self) {
+        if self.readback.get() != Readback::Copied {
+            return;
+        }
+        let mapped = self.mapped.clone();
+        self.stats_readback
+            .slice(..)
+            .map_async(wgpu::MapMode::Read, move |result| {
+                mapped.store(result.is_ok(), Ordering::Release);
+            });
+        self.readback.set(Readback::Mapping);
+    }
+
+    /// Visible and culled instances of a recent frame, `None` until a copy arrives.
+    pub fn read_stats(&self, device: &wgpu::Device) -> Option<CullStats> {
+        if self.readback.get() != Readback::Mapping {
+            return None;
+        }
+        device.poll(wgpu::Maintain::Poll);
+        if !self.mapped.swap(false, Ordering::Acquire) {
+            return None;
+        }
+        let visible = {
+            let data = self.stats_readback.slice(..).get_mapped_range();
+            *bytemuck::from_bytes::<u32>(&data)
+        };
+        self.stats_readback.unmap();
+        self.readback.set(Readback::Idle);
+        let instances = self.readback_instances.get();
+        Some(CullStats {
+            visible: visible as usize,
+            culled: instances.saturating_sub(visible) as usize,
+        })
+    }
+
+    pub fn indirect(&self) -> IndirectArgs<'_> {
+        IndirectArgs {
+            buffer: &self.args,
+            multi_draw: self.multi_draw,
+        }
+    }
+
+    fn bind_group(&self, device: &wgpu::Device, instances: &InstanceBinding) -> wgpu::BindGroup {
+        let buffers = [
+            &self.uniform,
+            &self.draws,
+            &self.draw_ids,
+            &self.args,
+            &self.visible_count,
+        ];
+        Self::create_bind_group(device, &self.layout, instances, buffers, &self.hiz)
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        instances: &InstanceBinding,
+        [uniform, draws, draw_ids, args, visible_count]: [&wgpu::Buffer; 5],
+        hiz: &HiZ,
+    ) -> wgpu::BindGroup {
+        let buffers = [
+            uniform,
+            instances.transforms(),
+            instances.indices(),
+            draws,
+            draw_ids,
+            args,
+            visible_count,
+        ];
+        let mut entries: Vec<_> = buffers
+            .iter()
+            .enumerate()
+            .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect();
+        entries.push(wgpu::BindGroupEntry {
+            binding: buffers.len() as u32,
+            resource: wgpu::BindingResource::TextureView(hiz.view()),
+        });
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Culling Bind Group"),
+            layout,
+            entries: &entries,
+        })
+    }
+}
+
+/// Arguments of the culled draws, one slot per [`Primitive::draw_slot`].
+#[derive(Clone, Copy)]
+pub struct IndirectArgs<'a> {
+    buffer: &'a wgpu::Buffer,
+    /// Consecutive indexed slots can be drawn at once
+    pub multi_draw: bool,
+}
+
+impl<'a> IndirectArgs<'a> {
+    pub fn draw(&self, pass: &mut wgpu::RenderPass<'a>, slot: u32) {
+        pass.draw_indirect(self.buffer, slot as u64 * ARGS_SIZE);
+    }
+
+    /// With `multi_draw` in a single call, otherwise one call per slot.
+    pub fn draw_indexed(&self, pass: &mut wgpu::RenderPass<'a>, slots: Range<u32>) {
+        if slots.is_empty() {
+            return;
+        }
+        if self.multi_draw {
+            let offset = slots.start as u64 * ARGS_SIZE;
+            pass.multi_draw_indexed_indirect(self.buffer, offset, slots.len() as u32);
+        } else {
+            for slot in slots {
+                pass.draw_indexed_indirect(self.buffer, slot as u64 * ARGS_SIZE);
+            }
+        }
+    }
+}
+
+/// Recreates the buffer at the next power of two size when `size` doesn't fit.
+fn grow(device: &wgpu::Device, buffer: &mut wgpu::Buffer, label: &str, size: u64) {
+    if size > buffer.size() {
+        *buffer = create_buffer(device, label, buffer.usage(), size.next_power_of_two());
+    }
+}
+
+fn create_buffer(
+    device: &wgpu::Device,
+    label: &str,
+    usage: wgpu::BufferUsages,
+    size: u64,
+) -> wgpu::Buffer {
+    // Bindings can't be empty
+    device.create_buffer(&wgpu::BufferDescriptor {
+        label: Some(label),
+        size: size.max(ARGS_SIZE * 4),
+        usage: usage | wgpu::BufferUsages::COPY_DST,
+        mapped_at_creation: false,
+    })
+}
@@ -0,0 +1,217 @@
+//! Hierarchical depth of the last frame for occlusion culling. Every level keeps the
+//! farthest depth of the texels it covers.
+
+use std::{borrow::Cow, num::NonZeroU32};
+
+use super::App;
+
+// `SAMPLE_COUNT` in hiz.wgsl
+const _: () = assert!(App::SAMPLE_COUNT == 4);
+
+const FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R32Float;
+const WORKGROUP_SIZE: u32 = 8;
+
+struct Pyramid {
+    /// All levels, read by the culling pass
+    view: wgpu::TextureView,
+    /// Sizes of the levels, largest first
+    sizes: Vec<(u32, u32)>,
+    /// One per level, each reading the level above or the depth buffer
+    bind_groups: Vec<wgpu::BindGroup>,
+}
+
+pub struct HiZ {
+    pyramid: Pyramid,
+    init_layout: wgpu::BindGroupLayout,
+    downsample_layout: wgpu::BindGroupLayout,
+    init_pipeline: wgpu::ComputePipeline,
+    downsample_pipeline: wgpu::ComputePipeline,
+}
+
+impl HiZ {
+    /// `depth` is the multisampled depth buffer of the main pass.
+    pub fn new(
+        device: &wgpu::Device,
+        depth: &wgpu::TextureView,
+        config: &wgpu::SurfaceConfiguration,
+    ) -> Self {
+        let destination_entry = wgpu::BindGroupLayoutEntry {
+            binding: 2,
+            visibility: wgpu::ShaderStages::COMPUTE,
+            ty: wgpu::BindingType::StorageTexture {
+                access: wgpu::StorageTextureAccess::WriteOnly,
+                format: FORMAT,
+                view_dimension: wgpu::TextureViewDimension::D2,
+            },
+            count: None,
+        };
+        let init_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Hi-Z Init Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: true,
+                    },
+                    count: None,
+                },
+                destination_entry,
+            ],
+        });
+        let downsample_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Hi-Z Downsample Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                destination_entry,
+            ],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("Hi-Z Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/shaders/hiz.wgsl"
+            )))),
+        });
+        let create_pipeline = |label, layout, entry_point| {
+            let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+                label: Some(label),
+                bind_group_layouts: &[layout],
+                push_constant_ranges: &[],
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: Some(&pipeline_layout),
+                module: &shader,
+                entry_point,
+            })
+        };
+
+        Self {
+            pyramid: Self::create_pyramid(device, depth, config, &init_layout, &downsample_layout),
+            init_pipeline: create_pipeline("Hi-Z Init Pipeline", &init_layout, "init"),
+            downsample_pipeline: create_pipeline(
+                "Hi-Z Downsample Pipeline",
+                &downsample_layout,
+                "downsample",
+            ),
+            init_layout,
+            downsample_layout,
+        }
+    }
+
+    pub fn resize(
+        &mut self,
+        device: &wgpu::Device,
+        depth: &wgpu::TextureView,
+        config: &wgpu::SurfaceConfiguration,
+    ) {
+        self.pyramid = Self::create_pyramid(
+            device,
+            depth,
+            config,
+            &self.init_layout,
+            &self.downsample_layout,
+        );
+    }
+
+    pub fn view(&self) -> &wgpu::TextureView {
+        &self.pyramid.view
+    }
+
+    pub fn levels(&self) -> u32 {
+        self.pyramid.sizes.len() as u32
+    }
+
+    /// Fills all levels from the depth buffer, after it was written.
+    pub fn build<'a>(&'a self, pass: &mut wgpu::ComputePass<'a>) {
+        let levels = self.pyramid.sizes.iter().zip(&self.pyramid.bind_groups);
+        for (level, (&(width, height), bind_group)) in levels.enumerate() {
+            let pipeline = match level {
+                0 => &self.init_pipeline,
+                _ => &self.downsample_pipeline,
+            };
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch_workgroups(
+                width.div_ceil(WORKGROUP_SIZE),
+                height.div_ceil(WORKGROUP_SIZE),
+                1,
+            );
+        }
+    }
+
+    fn create_pyramid(
+        device: &wgpu::Device,
+        depth: &wgpu::TextureView,
+        config: &wgpu::SurfaceConfiguration,
+        init_layout: &wgpu::BindGroupLayout,
+        downsample_layout: &wgpu::BindGroupLayout,
+    ) -> Pyramid {
+        let (width, height) = (config.width.max(1), config.height.max(1));
+        let level_count = u32::BITS - width.max(height).leading_zeros();
+        let texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Hi-Z Texture"),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: level_count,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format: FORMAT,
+            usage: wgpu::TextureUsages::STORAGE_BINDING | wgpu::TextureUsages::TEXTURE_BINDING,
+            view_formats: &[],
+        });
+        let level_views: Vec<_> = (0..level_count)
+            .map(|level| {
+                texture.create_view(&wgpu::TextureViewDescriptor {
+                    base_mip_level: level,
+                    mip_level_count: NonZeroU32::new(1),
+                    ..Default::default()
+                })
+            })
+            .collect();
+        let bind_groups = (0..level_views.len())
+            .map(|level| {
+                let (layout, source) = match level {
+                    0 => (init_layout, depth),
+                    _ => (downsample_layout, &level_views[level - 1]),
+                };
+                device.create_bind_group(&wgpu::BindGroupDescriptor {
+                    label: Some("Hi-Z Bind Group"),
+                    layout,
+                    entries: &[
+                        wgpu::BindGroupEntry {
+                            binding: if level == 0 { 0 } else { 1 },
+                            resource: wgpu::BindingResource::TextureView(source),
+                        },
+                        wgpu::BindGroupEntry {
+                            binding: 2,
+                            resource: wgpu::BindingResource::TextureView(&level_views[level]),
+                        },
+                    ],
+                })
+            })
+            .collect();
+        Pyramid {
+            view: texture.create_view(&Default::default()),
+            sizes: (0..level_count)
+                .map(|level| ((width >> level).max(1), (height >> level).max(1)))
+                .collect(),
+            bind_groups,
+        }
+    }
+}
@@ -0,0 +1,167 @@
+//! World and normal matrices of every drawn instance, in one storage buffer. Draws index
+//! it through a second buffer of instance indices, the identity unless culled on the GPU.
+
+use bytemuck::{Pod, Zeroable};
+use glam::{Mat3, Mat4};
+
+use crate::utils::NonZeroSized;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct InstanceTransform {
+    pub world: [[f32; 4]; 4],
+    /// Inverse transpose of the upper 3x3, columns padded like `mat3x3<f32>` in storage
+    pub normal: [[f32; 4]; 3],
+}
+
+impl InstanceTransform {
+    /// Singular transforms, like the zero scale that hides a node, get the identity normal
+    /// matrix instead of an inverse full of NaNs.
+    pub fn new(world: Mat4) -> Self {
+        let upper = Mat3::from_mat4(world);
+        let normal = if upper.determinant().abs() > f32::EPSILON {
+            upper.inverse().transpose()
+        } else {
+            Mat3::IDENTITY
+        };
+        Self {
+            world: world.to_cols_array_2d(),
+            normal: [
+                normal.x_axis.extend(0.).into(),
+                normal.y_axis.extend(0.).into(),
+                normal.z_axis.extend(0.).into(),
+            ],
+        }
+    }
+}
+
+pub struct InstanceBinding {
+    pub binding: wgpu::BindGroup,
+    pub layout: wgpu::BindGroupLayout,
+    buffer: wgpu::Buffer,
+    indices: wgpu::Buffer,
+}
+
+impl InstanceBinding {
+    pub const DESC: wgpu::BindGroupLayoutDescriptor<'static> = wgpu::BindGroupLayoutDescriptor {
+        label: Some("Instance Bind Group Layout"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: 0,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(InstanceTransform::NSIZE),
+                },
+                count: None,
+            },
+            wgpu::BindGroupLayoutEntry {
+                binding: 1,
+                visibility: wgpu::ShaderStages::VERTEX,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(u32::NSIZE),
+                },
+                count: None,
+            },
+        ],
+    };
+
+    pub fn new(device: &wgpu::Device) -> Self {
+        let layout = device.create_bind_group_layout(&Self::DESC);
+        let (buffer, indices) = Self::create_buffers(device, 1);
+        let binding = Self::create_bind_group(device, &layout, &buffer, &indices);
+        Self {
+            binding,
+            layout,
+            buffer,
+            indices,
+        }
+    }
+
+    /// Uploads the transforms and identity indices, the buffers grow when they don't fit.
+    pub fn update(
+        &mut self,
+        device: &wgpu::Device,
+        queue: &wgpu::Queue,
+        transforms: &[InstanceTransform],
+    ) {
+        if std::mem::size_of_val(transforms) as u64 > self.buffer.size() {
+            (self.buffer, self.indices) =
+                Self::create_buffers(device, transforms.len().next_power_of_two());
+            self.binding =
+                Self::create_bind_group(device, &self.layout, &self.buffer, &self.indices);
+        }
+        let indices: Vec<_> = (0..transforms.len() as u32).collect();
+        queue.write_buffer(&self.buffer, 0, bytemuck::cast_slice(transforms));
+        queue.write_buffer(&self.indices, 0, bytemuck::cast_slice(&indices));
+    }
+
+    pub fn transforms(&self) -> &wgpu::Buffer {
+        &self.buffer
+    }
+
+    /// Written by the culling compute pass when it is used.
+    pub fn indices(&self) -> &wgpu::Buffer {
+        &self.indices
+    }
+
+    fn create_buffers(device: &wgpu::Device, capacity: usize) -> (wgpu::Buffer, wgpu::Buffer) {
+        let create_buffer = |label, size| {
+            device.create_buffer(&wgpu::BufferDescriptor {
+                label: Some(label),
+                size: (capacity * size) as u64,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+                mapped_at_creation: false,
+            })
+        };
+        (
+            create_buffer("Instance Buffer", std::mem::size_of::<InstanceTransform>()),
+            create_buffer("Instance Index Buffer", std::mem::size_of::<u32>()),
+        )
+    }
+
+    fn create_bind_group(
+        device: &wgpu::Device,
+        layout: &wgpu::BindGroupLayout,
+        buffer: &wgpu::Buffer,
+        indices: &wgpu::Buffer,
+    ) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Instance Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: indices.as_entire_binding(),
+                },
+            ],
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+
+    #[test]
+    fn zero_scale_normal_matrix() {
+        let hidden = InstanceTransform::new(Mat4::from_scale(Vec3::ZERO));
+        assert!(hidden
+            .normal
+            .iter()
+            .flatten()
+            .all(|value| value.is_finite()));
+
+        let scaled = InstanceTransform::new(Mat4::from_scale(Vec3::new(2., 1., 1.)));
+        assert_eq!(scaled.normal[0], [0.5, 0., 0., 0.]);
+    }
+}
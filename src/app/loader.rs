@@ -0,0 +1,201 @@
+use std::{
+    collections::HashMap,
+    iter::zip,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{self, TryRecvError},
+        Arc,
+    },
+    thread,
+};
+
+use color_eyre::{eyre::eyre, Result};
+use glam::Mat4;
+use image::RgbaImage;
+
+use super::{Indices, MeshVertex};
+use crate::{
+    gltf::{convert_specular_glossiness, generate_mips, GltfDocument, LoadProgress},
+    mesh::MeshDocument,
+    utils::{par_map, UnwrapRepeat},
+};
+
+pub struct PreparedPrimitive {
+    pub vertices: Vec<MeshVertex>,
+    pub indices: Option<Indices>,
+}
+
+/// Spec-gloss material baked to metallic-roughness, images with their mip chains.
+pub struct BakedMaterial {
+    pub base_color_factor: [f32; 4],
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub base_color_mips: Option<Vec<RgbaImage>>,
+    pub metallic_roughness_mips: Option<Vec<RgbaImage>>,
+    pub texture: Option<usize>,
+}
+
+/// Everything of a model that doesn't need the device, ready for upload.
+pub struct PreparedModel {
+    pub gltf: GltfDocument,
+    /// `EXT_mesh_gpu_instancing` transforms by node index
+    pub instance_transforms: HashMap<usize, Vec<Mat4>>,
+    /// Keyed by mesh and primitive index, primitives without positions are skipped
+    pub primitives: HashMap<(usize, usize), PreparedPrimitive>,
+    /// Keyed by material index
+    pub baked_materials: HashMap<usize, BakedMaterial>,
+}
+
+impl PreparedModel {
+    pub fn new(gltf: GltfDocument, progress: &LoadProgress) -> Result<Self> {
+        let materials: Vec<_> = gltf.document.materials().collect();
+        let baked_materials = par_map(&materials, |material| {
+            let (Some(index), Some(spec_gloss)) =
+                (material.index(), material.pbr_specular_glossiness())
+            else {
+                return Ok(None);
+            };
+            let converted = convert_specular_glossiness(&gltf, &spec_gloss)?;
+            let baked = BakedMaterial {
+                base_color_factor: converted.base_color_factor,
+                metallic_factor: converted.metallic_factor,
+                roughness_factor: converted.roughness_factor,
+                base_color_mips: converted.base_color_image.map(generate_mips),
+                metallic_roughness_mips: converted.metallic_roughness_image.map(generate_mips),
+                texture: converted.texture,
+            };
+            Ok(Some((index, baked)))
+        })
+        .into_iter()
+        .filter_map(Result::transpose)
+        .collect::<Result<_>>()?;
+
+        let mut instance_transforms = HashMap::new();
+        for node in gltf.document.nodes() {
+            if let Some(transforms) = gltf.instance_transforms(&node)? {
+                instance_transforms.insert(node.index(), transforms);
+            }
+        }
+
+        let gltf_primitives: Vec<_> = gltf
+            .document
+            .meshes()
+            .flat_map(|mesh| {
+                mesh.primitives()
+                    .map(move |primitive| (mesh.index(), primitive))
+            })
+            .collect();
+        LoadProgress::add(&progress.meshes_total, gltf_primitives.len());
+        let primitives = par_map(&gltf_primitives, |(mesh, primitive)| {
+            let prepared = Self::prepare_primitive(&gltf, primitive)?;
+            LoadProgress::add(&progress.meshes_done, 1);
+            Ok(prepared.map(|prepared| ((*mesh, primitive.index()), prepared)))
+        })
+        .into_iter()
+        .filter_map(Result::transpose)
+        .collect::<Result<_>>()?;
+
+        Ok(Self {
+            gltf,
+            instance_transforms,
+            primitives,
+            baked_materials,
+        })
+    }
+
+    /// Interleaves vertex attributes, `None` for primitives without positions.
+    fn prepare_primitive(
+        gltf: &GltfDocument,
+        primitive: &gltf::Primitive,
+    ) -> Result<Option<PreparedPrimitive>> {
+        let Some(positions) = gltf.read_attribute(primitive, gltf::Semantic::Positions)? else {
+            return Ok(None);
+        };
+        let normals = gltf
+            .read_attribute(primitive, gltf::Semantic::Normals)?
+            .map(Vec::into_iter);
+        let tex_coords = gltf
+            .read_attribute(primitive, gltf::Semantic::TexCoords(0))?
+            .map(Vec::into_iter);
+        let colors = gltf.read_colors(primitive)?;
+        let vertices = zip(
+            zip(positions, normals.unwrap_repeat()),
+            tex_coords.unwrap_repeat(),
+        )
+        .enumerate()
+        .map(|(index, ((position, normal), tex_coord))| MeshVertex {
+            position,
+            normal,
+            tex_coord,
+            color: colors.as_ref().map_or([1.; 4], |colors| colors[index]),
+        })
+        .collect();
+        let indices = primitive
+            .indices()
+            .map(|indices| gltf.read_indices(&indices))
+            .transpose()?;
+        Ok(Some(PreparedPrimitive { vertices, indices }))
+    }
+}
+
+pub enum LoadedModel {
+    Gltf(Box<PreparedModel>),
+    Mesh(MeshDocument),
+}
+
+impl LoadedModel {
+    /// Picks the importer by file extension, glTF unless it's one of the mesh formats.
+    pub fn import(path: &Path, progress: &LoadProgress) -> Result<Self> {
+        if MeshDocument::is_supported(path) {
+            MeshDocument::import(path).map(Self::Mesh)
+        } else {
+            let gltf = GltfDocument::import_with_progress(path, progress)?;
+            PreparedModel::new(gltf, progress).map(|model| Self::Gltf(Box::new(model)))
+        }
+    }
+}
+
+/// Imports and prepares a model on a worker thread.
+pub struct ModelLoader {
+    pub path: PathBuf,
+    progress: Arc<LoadProgress>,
+    receiver: mpsc::Receiver<Result<LoadedModel>>,
+}
+
+impl ModelLoader {
+    pub fn spawn(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let progress = Arc::new(LoadProgress::default());
+        let (sender, receiver) = mpsc::channel();
+        {
+            let path = path.clone();
+            let progress = progress.clone();
+            thread::spawn(move || {
+                let model = LoadedModel::import(&path, &progress);
+                // The receiving side may have been dropped, nothing left to do then
+                let _ = sender.send(model);
+            });
+        }
+        Self {
+            path,
+            progress,
+            receiver,
+        }
+    }
+
+    pub fn progress(&self) -> &LoadProgress {
+        &self.progress
+    }
+
+    /// Returns the prepared model once the worker finishes, never blocks.
+    pub fn try_finish(&self) -> Option<Result<LoadedModel>> {
+        match self.receiver.try_recv() {
+            Ok(model) => Some(model),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(eyre!(
+                "Loader thread of {:?} exited without a model.",
+                self.path
+            ))),
+        }
+    }
+}
@@ -0,0 +1,190 @@
+use bytemuck::{Pod, Zeroable};
+
+use crate::{gltf::MaterialExtensions, utils::NonZeroSized};
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Pod, Zeroable)]
+pub struct MaterialUniform {
+    pub base_color_factor: [f32; 3],
+    pub alpha_cutoff: f32,
+    pub specular_color_factor: [f32; 3],
+    pub specular_factor: f32,
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub anisotropy_strength: f32,
+    pub anisotropy_rotation: f32,
+    pub iridescence_factor: f32,
+    pub iridescence_ior: f32,
+    pub iridescence_thickness_minimum: f32,
+    pub iridescence_thickness_maximum: f32,
+    pub normal_scale: f32,
+    pub _padding: [f32; 3],
+}
+
+impl Default for MaterialUniform {
+    /// glTF defaults for every property
+    fn default() -> Self {
+        Self {
+            base_color_factor: [1.; 3],
+            alpha_cutoff: 0.5,
+            specular_color_factor: [1.; 3],
+            specular_factor: 1.,
+            metallic_factor: 1.,
+            roughness_factor: 1.,
+            anisotropy_strength: 0.,
+            anisotropy_rotation: 0.,
+            iridescence_factor: 0.,
+            iridescence_ior: 1.3,
+            iridescence_thickness_minimum: 100.,
+            iridescence_thickness_maximum: 400.,
+            normal_scale: 1.,
+            _padding: [0.; 3],
+        }
+    }
+}
+
+impl MaterialUniform {
+    pub fn new(material: &gltf::Material, extensions: &MaterialExtensions) -> Self {
+        let pbr = material.pbr_metallic_roughness();
+        let [r, g, b, _] = pbr.base_color_factor();
+        let mut uniform = Self {
+            base_color_factor: [r, g, b],
+            alpha_cutoff: material.alpha_cutoff().unwrap_or(0.5),
+            metallic_factor: pbr.metallic_factor(),
+            roughness_factor: pbr.roughness_factor(),
+            normal_scale: material.normal_texture().map_or(1., |t| t.scale()),
+            ..Default::default()
+        };
+        if let Some(specular) = extensions.specular {
+            uniform.specular_factor = specular.factor;
+            uniform.specular_color_factor = specular.color_factor;
+        }
+        if let Some(iridescence) = extensions.iridescence {
+            uniform.iridescence_factor = iridescence.factor;
+            uniform.iridescence_ior = iridescence.ior;
+            uniform.iridescence_thickness_minimum = iridescence.thickness_minimum;
+            uniform.iridescence_thickness_maximum = iridescence.thickness_maximum;
+        }
+        if let Some(anisotropy) = extensions.anisotropy {
+            uniform.anisotropy_strength = anisotropy.strength;
+            uniform.anisotropy_rotation = anisotropy.rotation;
+        }
+        uniform
+    }
+}
+
+/// Optional parts of the BRDF, each one enables a define in the mesh shader.
+#[derive(Debug, Default, Eq, PartialEq, Hash, Clone, Copy)]
+pub struct MaterialFeatures {
+    /// Without one the texture isn't sampled and counts as white
+    pub base_color_texture: bool,
+    pub metallic_roughness_texture: bool,
+    pub normal_map: bool,
+    pub specular: bool,
+    pub iridescence: bool,
+    pub anisotropy: bool,
+    /// Without one the direction is the tangent at full strength
+    pub anisotropy_texture: bool,
+}
+
+impl MaterialFeatures {
+    pub fn new(material: &gltf::Material, extensions: &MaterialExtensions) -> Self {
+        let pbr = material.pbr_metallic_roughness();
+        Self {
+            base_color_texture: pbr.base_color_texture().is_some(),
+            metallic_roughness_texture: pbr.metallic_roughness_texture().is_some(),
+            normal_map: material.normal_texture().is_some(),
+            specular: extensions.specular.is_some(),
+            iridescence: extensions.iridescence.is_some(),
+            anisotropy: extensions.anisotropy.is_some(),
+            anisotropy_texture: extensions.anisotropy.is_some_and(|a| a.texture.is_some()),
+        }
+    }
+
+    pub fn shader_defines(&self) -> Vec<&'static str> {
+        let mut defines = vec![];
+        if self.base_color_texture {
+            defines.push("BASE_COLOR_TEXTURE");
+        }
+        if self.metallic_roughness_texture {
+            defines.push("METALLIC_ROUGHNESS_TEXTURE");
+        }
+        if self.normal_map {
+            defines.push("NORMAL_MAP");
+        }
+        if self.specular {
+            defines.push("KHR_MATERIALS_SPECULAR");
+        }
+        if self.iridescence {
+            defines.push("KHR_MATERIALS_IRIDESCENCE");
+        }
+        if self.anisotropy {
+            defines.push("KHR_MATERIALS_ANISOTROPY");
+        }
+        if self.anisotropy_texture {
+            defines.push("ANISOTROPY_TEXTURE");
+        }
+        if self.specular || self.iridescence || self.anisotropy {
+            defines.push("MATERIAL_EXTENSIONS");
+        }
+        defines
+    }
+}
+
+/// Binding slots of the material bind group.
+pub mod binding {
+    pub const UNIFORM: u32 = 0;
+    pub const BASE_COLOR_TEXTURE: u32 = 1;
+    pub const SAMPLER: u32 = 2;
+    pub const SPECULAR_TEXTURE: u32 = 3;
+    pub const SPECULAR_COLOR_TEXTURE: u32 = 4;
+    pub const IRIDESCENCE_TEXTURE: u32 = 5;
+    pub const IRIDESCENCE_THICKNESS_TEXTURE: u32 = 6;
+    pub const ANISOTROPY_TEXTURE: u32 = 7;
+    pub const METALLIC_ROUGHNESS_TEXTURE: u32 = 8;
+    pub const NORMAL_TEXTURE: u32 = 9;
+}
+
+const fn texture_entry(binding: u32) -> wgpu::BindGroupLayoutEntry {
+    wgpu::BindGroupLayoutEntry {
+        binding,
+        visibility: wgpu::ShaderStages::FRAGMENT,
+        ty: wgpu::BindingType::Texture {
+            sample_type: wgpu::TextureSampleType::Float { filterable: true },
+            view_dimension: wgpu::TextureViewDimension::D2,
+            multisampled: false,
+        },
+        count: None,
+    }
+}
+
+pub const MATERIAL_LAYOUT_DESC: wgpu::BindGroupLayoutDescriptor<'static> =
+    wgpu::BindGroupLayoutDescriptor {
+        label: Some("Material Bind Group Layout"),
+        entries: &[
+            wgpu::BindGroupLayoutEntry {
+                binding: binding::UNIFORM,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Buffer {
+                    ty: wgpu::BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: Some(MaterialUniform::NSIZE),
+                },
+                count: None,
+            },
+            texture_entry(binding::BASE_COLOR_TEXTURE),
+            wgpu::BindGroupLayoutEntry {
+                binding: binding::SAMPLER,
+                visibility: wgpu::ShaderStages::FRAGMENT,
+                ty: wgpu::BindingType::Sampler(wgpu::SamplerBindingType::Filtering),
+                count: None,
+            },
+            texture_entry(binding::SPECULAR_TEXTURE),
+            texture_entry(binding::SPECULAR_COLOR_TEXTURE),
+            texture_entry(binding::IRIDESCENCE_TEXTURE),
+            texture_entry(binding::IRIDESCENCE_THICKNESS_TEXTURE),
+            texture_entry(binding::ANISOTROPY_TEXTURE),
+            texture_entry(binding::METALLIC_ROUGHNESS_TEXTURE),
+            texture_entry(binding::NORMAL_TEXTURE),
+        ],
+    };
@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+use glam::{Mat3, Mat4, Vec3};
+
+use super::{MaterialFeatures, Scene};
+use crate::gltf::MaterialExtensions;
+
+/// Handle of a model added to the [`App`](super::App).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ModelId(pub(crate) usize);
+
+/// Axis aligned bounding box.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Bounds {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Bounds {
+    /// Contains nothing, the identity of [`Bounds::union`].
+    pub const EMPTY: Self = Self {
+        min: Vec3::splat(f32::INFINITY),
+        max: Vec3::splat(f32::NEG_INFINITY),
+    };
+
+    pub fn from_points(points: impl IntoIterator<Item = Vec3>) -> Self {
+        points.into_iter().fold(Self::EMPTY, |bounds, point| Self {
+            min: bounds.min.min(point),
+            max: bounds.max.max(point),
+        })
+    }
+
+    /// From the `min` and `max` of a POSITION accessor, `None` when they are missing or
+    /// don't match the dequantized positions.
+    pub fn from_accessor(accessor: &gltf::Accessor) -> Option<Self> {
+        if accessor.normalized() {
+            return None;
+        }
+        let vec3 = |value: gltf::json::Value| {
+            let [x, y, z] = value.as_array()?.as_slice() else {
+                return None;
+            };
+            Some(Vec3::new(
+                x.as_f64()? as f32,
+                y.as_f64()? as f32,
+                z.as_f64()? as f32,
+            ))
+        };
+        Some(Self {
+            min: vec3(accessor.min()?)?,
+            max: vec3(accessor.max()?)?,
+        })
+    }
+
+    pub fn union(self, other: Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    /// Axis aligned box around the transformed box.
+    pub fn transform(&self, transform: Mat4) -> Self {
+        let linear = Mat3::from_mat4(transform);
+        let linear = Mat3::from_cols(
+            linear.x_axis.abs(),
+            linear.y_axis.abs(),
+            linear.z_axis.abs(),
+        );
+        let center = transform.transform_point3(self.center());
+        let extents = linear * (self.max - self.min) / 2.;
+        Self {
+            min: center - extents,
+            max: center + extents,
+        }
+    }
+
+    pub fn center(&self) -> Vec3 {
+        (self.min + self.max) / 2.
+    }
+}
+
+/// Materials are owned by the model they came from.
+pub type MaterialKey = (ModelId, Option<usize>);
+
+/// Material properties that select the pipeline a primitive is drawn with.
+#[derive(Debug, Clone, Copy)]
+pub struct MaterialInfo {
+    pub double_sided: bool,
+    pub alpha_mode: gltf::material::AlphaMode,
+    pub features: MaterialFeatures,
+}
+
+impl Default for MaterialInfo {
+    /// glTF default material
+    fn default() -> Self {
+        Self {
+            double_sided: false,
+            alpha_mode: gltf::material::AlphaMode::Opaque,
+            features: MaterialFeatures::default(),
+        }
+    }
+}
+
+impl MaterialInfo {
+    pub fn new(material: &gltf::Material) -> Self {
+        Self {
+            double_sided: material.double_sided(),
+            alpha_mode: material.alpha_mode(),
+            features: MaterialFeatures::new(material, &MaterialExtensions::from_material(material)),
+        }
+    }
+}
+
+/// Primitive with `KHR_materials_variants` mappings.
+#[derive(Debug)]
+pub struct VariantMapping {
+    /// Mesh and primitive index in the source document
+    pub source: (usize, usize),
+    pub topology: wgpu::PrimitiveTopology,
+    pub default_material: Option<usize>,
+    pub current_material: Option<usize>,
+    /// Material for each variant index that overrides the default one
+    pub materials: HashMap<usize, usize>,
+}
+
+#[derive(Debug, Default)]
+pub struct Model {
+    /// Names of `KHR_materials_variants` variants
+    pub variants: Vec<String>,
+    pub active_variant: Option<usize>,
+    pub materials: HashMap<Option<usize>, MaterialInfo>,
+    pub variant_mappings: Vec<VariantMapping>,
+    pub scene: Scene,
+}
+
+impl Model {
+    pub fn active_variant_name(&self) -> Option<&str> {
+        self.active_variant
+            .map(|index| self.variants[index].as_str())
+    }
+}
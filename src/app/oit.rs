@@ -0,0 +1,223 @@
+//! Weighted blended order-independent transparency, McGuire and Bavoil 2013.
+
+use std::borrow::Cow;
+
+use super::App;
+
+/// How primitives of `AlphaMode::Blend` materials are drawn.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TransparencyMode {
+    /// Blended back-to-front by object, wrong where objects intersect
+    #[default]
+    Sorted,
+    /// Weighted average of all layers of a pixel, independent of draw order
+    WeightedBlended,
+}
+
+impl TransparencyMode {
+    /// `WGLTF_TRANSPARENCY=oit` selects [`TransparencyMode::WeightedBlended`].
+    pub fn from_env() -> Self {
+        match std::env::var("WGLTF_TRANSPARENCY").as_deref() {
+            Ok("oit") => Self::WeightedBlended,
+            _ => Self::Sorted,
+        }
+    }
+}
+
+const ACCUM_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::Rgba16Float;
+const REVEALAGE_FORMAT: wgpu::TextureFormat = wgpu::TextureFormat::R8Unorm;
+
+/// Color targets of transparent pipelines: weighted premultiplied color summed
+/// up and the product of `1 - alpha`.
+pub const OIT_TARGETS: [Option<wgpu::ColorTargetState>; 2] = [
+    Some(wgpu::ColorTargetState {
+        format: ACCUM_FORMAT,
+        blend: Some(wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::One,
+                dst_factor: wgpu::BlendFactor::One,
+                operation: wgpu::BlendOperation::Add,
+            },
+        }),
+        write_mask: wgpu::ColorWrites::ALL,
+    }),
+    Some(wgpu::ColorTargetState {
+        format: REVEALAGE_FORMAT,
+        blend: Some(wgpu::BlendState {
+            color: wgpu::BlendComponent {
+                src_factor: wgpu::BlendFactor::Zero,
+                dst_factor: wgpu::BlendFactor::OneMinusSrc,
+                operation: wgpu::BlendOperation::Add,
+            },
+            alpha: wgpu::BlendComponent::REPLACE,
+        }),
+        write_mask: wgpu::ColorWrites::ALL,
+    }),
+];
+
+struct Targets {
+    accum: wgpu::TextureView,
+    revealage: wgpu::TextureView,
+    accum_resolved: wgpu::TextureView,
+    revealage_resolved: wgpu::TextureView,
+    bind_group: wgpu::BindGroup,
+}
+
+pub struct WeightedBlendedOit {
+    targets: Targets,
+    bind_group_layout: wgpu::BindGroupLayout,
+    pipeline: wgpu::RenderPipeline,
+}
+
+impl WeightedBlendedOit {
+    pub fn new(device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) -> Self {
+        let texture_entry = |binding| wgpu::BindGroupLayoutEntry {
+            binding,
+            visibility: wgpu::ShaderStages::FRAGMENT,
+            ty: wgpu::BindingType::Texture {
+                sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                view_dimension: wgpu::TextureViewDimension::D2,
+                multisampled: false,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("OIT Composite Bind Group Layout"),
+            entries: &[texture_entry(0), texture_entry(1)],
+        });
+        let layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("OIT Composite Pipeline Layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+            label: Some("OIT Composite Shader"),
+            source: wgpu::ShaderSource::Wgsl(Cow::Borrowed(include_str!(concat!(
+                env!("CARGO_MANIFEST_DIR"),
+                "/shaders/oit_composite.wgsl"
+            )))),
+        });
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("OIT Composite Pipeline"),
+            layout: Some(&layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs_main",
+                buffers: &[],
+            },
+            primitive: wgpu::PrimitiveState::default(),
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs_main",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format: config.format,
+                    blend: Some(wgpu::BlendState::ALPHA_BLENDING),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState {
+                count: App::SAMPLE_COUNT,
+                ..Default::default()
+            },
+            multiview: None,
+        });
+
+        Self {
+            targets: Self::create_targets(device, config, &bind_group_layout),
+            bind_group_layout,
+            pipeline,
+        }
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, config: &wgpu::SurfaceConfiguration) {
+        self.targets = Self::create_targets(device, config, &self.bind_group_layout);
+    }
+
+    /// Cleared accumulation and revealage attachments, resolved for the composite.
+    pub fn color_attachments(&self) -> [Option<wgpu::RenderPassColorAttachment<'_>>; 2] {
+        let attachment = |view, resolve_target, clear| {
+            Some(wgpu::RenderPassColorAttachment {
+                view,
+                resolve_target: Some(resolve_target),
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(clear),
+                    store: false,
+                },
+            })
+        };
+        let Targets {
+            accum,
+            revealage,
+            accum_resolved,
+            revealage_resolved,
+            ..
+        } = &self.targets;
+        [
+            attachment(accum, accum_resolved, wgpu::Color::TRANSPARENT),
+            attachment(revealage, revealage_resolved, wgpu::Color::WHITE),
+        ]
+    }
+
+    /// Blends the resolved layers over the pass target.
+    pub fn composite<'a>(&'a self, pass: &mut wgpu::RenderPass<'a>) {
+        pass.set_pipeline(&self.pipeline);
+        pass.set_bind_group(0, &self.targets.bind_group, &[]);
+        pass.draw(0..3, 0..1);
+    }
+
+    fn create_targets(
+        device: &wgpu::Device,
+        config: &wgpu::SurfaceConfiguration,
+        bind_group_layout: &wgpu::BindGroupLayout,
+    ) -> Targets {
+        let create_view = |label, format, sample_count| {
+            let texture = device.create_texture(&wgpu::TextureDescriptor {
+                label: Some(label),
+                size: wgpu::Extent3d {
+                    width: config.width,
+                    height: config.height,
+                    depth_or_array_layers: 1,
+                },
+                mip_level_count: 1,
+                sample_count,
+                dimension: wgpu::TextureDimension::D2,
+                format,
+                usage: wgpu::TextureUsages::RENDER_ATTACHMENT
+                    | wgpu::TextureUsages::TEXTURE_BINDING,
+                view_formats: &[],
+            });
+            texture.create_view(&Default::default())
+        };
+        let accum = create_view("OIT Accum", ACCUM_FORMAT, App::SAMPLE_COUNT);
+        let revealage = create_view("OIT Revealage", REVEALAGE_FORMAT, App::SAMPLE_COUNT);
+        let accum_resolved = create_view("OIT Accum Resolved", ACCUM_FORMAT, 1);
+        let revealage_resolved = create_view("OIT Revealage Resolved", REVEALAGE_FORMAT, 1);
+        let bind_group = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("OIT Composite Bind Group"),
+            layout: bind_group_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: wgpu::BindingResource::TextureView(&accum_resolved),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::TextureView(&revealage_resolved),
+                },
+            ],
+        });
+        Targets {
+            accum,
+            revealage,
+            accum_resolved,
+            revealage_resolved,
+            bind_group,
+        }
+    }
+}
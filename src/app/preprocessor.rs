@@ -0,0 +1,92 @@
+/// Resolves `#ifdef NAME`, `#ifndef NAME`, `#else` and `#endif` blocks in WGSL source.
+///
+/// Directives must stand on their own line and may be nested. Lines of inactive
+/// branches are dropped, everything else is passed through untouched.
+pub fn preprocess(source: &str, defines: &[&str]) -> String {
+    let mut output = String::with_capacity(source.len());
+    // Each entry is (branch is active, enclosing block is active)
+    let mut stack: Vec<(bool, bool)> = vec![];
+    let is_active = |stack: &[(bool, bool)]| stack.last().is_none_or(|&(active, _)| active);
+
+    for line in source.lines() {
+        let trimmed = line.trim();
+        if let Some(name) = trimmed.strip_prefix("#ifdef ") {
+            let parent = is_active(&stack);
+            stack.push((parent && defines.contains(&name.trim()), parent));
+        } else if let Some(name) = trimmed.strip_prefix("#ifndef ") {
+            let parent = is_active(&stack);
+            stack.push((parent && !defines.contains(&name.trim()), parent));
+        } else if trimmed == "#else" {
+            if let Some((active, parent)) = stack.last_mut() {
+                *active = *parent && !*active;
+            }
+        } else if trimmed == "#endif" {
+            stack.pop();
+        } else if is_active(&stack) {
+            output.push_str(line);
+            output.push('\n');
+        }
+    }
+
+    output
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_blocks() {
+        let source = "\
+a
+#ifdef OUTER
+b
+    #ifdef INNER
+c
+    #endif
+d
+#endif
+e
+";
+        assert_eq!(preprocess(source, &["OUTER", "INNER"]), "a\nb\nc\nd\ne\n");
+        assert_eq!(preprocess(source, &["OUTER"]), "a\nb\nd\ne\n");
+        // An inner define can't activate a block inside an inactive one
+        assert_eq!(preprocess(source, &["INNER"]), "a\ne\n");
+    }
+
+    #[test]
+    fn else_branches() {
+        let source = "\
+#ifdef A
+a
+#else
+not a
+#ifndef B
+not b
+#else
+b
+#endif
+#endif
+";
+        assert_eq!(preprocess(source, &["A"]), "a\n");
+        assert_eq!(preprocess(source, &[]), "not a\nnot b\n");
+        assert_eq!(preprocess(source, &["B"]), "not a\nb\n");
+        assert_eq!(preprocess(source, &["A", "B"]), "a\n");
+    }
+
+    #[test]
+    fn undefined_symbol_in_active_block() {
+        let source = "\
+#ifdef A
+a
+#ifdef UNDEFINED
+undefined
+#else
+fallback
+#endif
+still a
+#endif
+";
+        assert_eq!(preprocess(source, &["A"]), "a\nfallback\nstill a\n");
+    }
+}
@@ -0,0 +1,192 @@
+//! Draw order of a frame: opaque front-to-back, then alpha-mask, then blend back-to-front.
+
+use std::{cmp::Ordering, ops::Range};
+
+use gltf::material::AlphaMode;
+
+use super::{geometry::GeometryArena, gpu_culling::IndirectArgs, DrawMode, Primitive};
+
+/// Instances of a primitive in one draw, see [`Primitive::draws`].
+pub struct DrawItem<'a> {
+    pub pipeline: &'a wgpu::RenderPipeline,
+    pub material: &'a wgpu::BindGroup,
+    pub primitive: &'a Primitive,
+    pub instances: Range<u32>,
+    pub key: SortKey,
+}
+
+/// Position of a draw in the frame, free of GPU resources.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SortKey {
+    pub alpha_mode: AlphaMode,
+    /// Squared distance from the camera to the world-space bounds center
+    pub distance: f32,
+    /// Model, mesh and primitive index, breaks ties so equal distances draw stably
+    pub order: (usize, (usize, usize)),
+    /// Indirect arguments of the draw, see [`Primitive::draw_slot`]
+    pub draw_slot: u32,
+}
+
+impl SortKey {
+    /// Within one alpha mode: blend back to front, opaque and alpha-mask front to back or
+    /// in draw slot order when `indirect`.
+    pub fn cmp(&self, other: &Self, indirect: bool) -> Ordering {
+        let by_distance = match self.alpha_mode {
+            AlphaMode::Opaque | AlphaMode::Mask if indirect => {
+                return self.draw_slot.cmp(&other.draw_slot);
+            }
+            AlphaMode::Opaque | AlphaMode::Mask => self.distance.total_cmp(&other.distance),
+            AlphaMode::Blend => other.distance.total_cmp(&self.distance),
+        };
+        by_distance.then_with(|| self.order.cmp(&other.order))
+    }
+}
+
+/// Sorts items of one alpha mode, see [`SortKey::cmp`].
+pub fn sort_by_key<T>(items: &mut [T], indirect: bool, key: impl Fn(&T) -> &SortKey) {
+    items.sort_by(|a, b| key(a).cmp(key(b), indirect));
+}
+
+#[derive(Default)]
+pub struct RenderQueue<'a> {
+    opaque: Vec<DrawItem<'a>>,
+    mask: Vec<DrawItem<'a>>,
+    blend: Vec<DrawItem<'a>>,
+}
+
+impl<'a> RenderQueue<'a> {
+    pub fn push(&mut self, item: DrawItem<'a>) {
+        match item.key.alpha_mode {
+            AlphaMode::Opaque => self.opaque.push(item),
+            AlphaMode::Mask => self.mask.push(item),
+            AlphaMode::Blend => self.blend.push(item),
+        }
+    }
+
+    /// Named buckets in draw order, each sorted. Indirect draws keep opaque and alpha-mask
+    /// primitives in draw slot order instead, so neighbors share state and batch.
+    pub fn into_buckets(mut self, indirect: bool) -> [(&'static str, Vec<DrawItem<'a>>); 3] {
+        for bucket in [&mut self.opaque, &mut self.mask, &mut self.blend] {
+            sort_by_key(bucket, indirect, |item| &item.key);
+        }
+        [
+            ("Opaque", self.opaque),
+            ("Mask", self.mask),
+            ("Blend", self.blend),
+        ]
+    }
+}
+
+/// Records the items in order, skipping state changes between draws that share it. With
+/// `indirect` arguments, consecutive indexed slots are merged into multi-draws.
+pub fn draw_items<'a>(
+    pass: &mut wgpu::RenderPass<'a>,
+    geometry: &'a GeometryArena,
+    items: Vec<DrawItem<'a>>,
+    indirect: Option<IndirectArgs<'a>>,
+) {
+    geometry.bind_vertices(pass);
+    let mut current_pipeline = None;
+    let mut current_material = None;
+    let mut current_index_format = None;
+    let mut batch = 0..0;
+    for item in items {
+        let primitive = item.primitive;
+        let index_format = match primitive.draw_mode {
+            DrawMode::Normal => None,
+            DrawMode::Indexed { format, .. } => Some(format),
+        };
+        let pipeline_changes = current_pipeline.is_none_or(|p| !std::ptr::eq(p, item.pipeline));
+        let material_changes = current_material.is_none_or(|m| !std::ptr::eq(m, item.material));
+        if let Some(indirect) = indirect {
+            let extends_batch = indirect.multi_draw
+                && !batch.is_empty()
+                && batch.end == item.key.draw_slot
+                && index_format.is_some_and(|format| current_index_format == Some(format));
+            if extends_batch && !pipeline_changes && !material_changes {
+                batch.end += 1;
+                continue;
+            }
+            indirect.draw_indexed(pass, std::mem::replace(&mut batch, 0..0));
+        }
+
+        if pipeline_changes {
+            pass.set_pipeline(item.pipeline);
+            current_pipeline = Some(item.pipeline);
+        }
+        if material_changes {
+            pass.set_bind_group(3, item.material, &[]);
+            current_material = Some(item.material);
+        }
+        if let Some(format) = index_format.filter(|&format| current_index_format != Some(format)) {
+            geometry.bind_indices(pass, format);
+            current_index_format = Some(format);
+        }
+
+        let instances = item.instances;
+        let slot = item.key.draw_slot;
+        match (indirect, &primitive.draw_mode) {
+            (None, DrawMode::Normal) => pass.draw(primitive.vertices.clone(), instances),
+            (None, DrawMode::Indexed { indices, .. }) => {
+                pass.draw_indexed(indices.clone(), primitive.vertices.start as i32, instances)
+            }
+            (Some(indirect), DrawMode::Normal) => indirect.draw(pass, slot),
+            (Some(_), DrawMode::Indexed { .. }) => batch = slot..slot + 1,
+        }
+    }
+    if let Some(indirect) = indirect {
+        indirect.draw_indexed(pass, batch);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(alpha_mode: AlphaMode, distance: f32, order: usize, draw_slot: u32) -> SortKey {
+        SortKey {
+            alpha_mode,
+            distance,
+            order: (0, (order, 0)),
+            draw_slot,
+        }
+    }
+
+    fn sorted(mut keys: Vec<SortKey>, indirect: bool) -> Vec<usize> {
+        sort_by_key(&mut keys, indirect, |key| key);
+        keys.iter().map(|key| key.order.1 .0).collect()
+    }
+
+    #[test]
+    fn draw_order() {
+        for alpha_mode in [AlphaMode::Opaque, AlphaMode::Mask] {
+            let keys = vec![
+                key(alpha_mode, 9., 0, 1),
+                key(alpha_mode, 1., 1, 2),
+                key(alpha_mode, 4., 2, 0),
+            ];
+            assert_eq!(sorted(keys.clone(), false), [1, 2, 0]);
+            assert_eq!(sorted(keys, true), [2, 0, 1]);
+        }
+
+        let keys = vec![
+            key(AlphaMode::Blend, 1., 0, 0),
+            key(AlphaMode::Blend, 9., 1, 1),
+            key(AlphaMode::Blend, 4., 2, 2),
+        ];
+        assert_eq!(sorted(keys.clone(), false), [1, 2, 0]);
+        assert_eq!(sorted(keys, true), [1, 2, 0]);
+    }
+
+    #[test]
+    fn ties_keep_source_order() {
+        for alpha_mode in [AlphaMode::Opaque, AlphaMode::Mask, AlphaMode::Blend] {
+            let keys = vec![
+                key(alpha_mode, 4., 2, 0),
+                key(alpha_mode, 4., 0, 0),
+                key(alpha_mode, 4., 1, 0),
+            ];
+            assert_eq!(sorted(keys, false), [0, 1, 2]);
+        }
+    }
+}
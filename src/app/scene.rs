@@ -0,0 +1,290 @@
+//! CPU copy of what a model draws, kept in sync with edits so it can be exported.
+
+use std::{collections::HashMap, io::Cursor, ops::Range, path::PathBuf};
+
+use color_eyre::Result;
+use glam::Mat4;
+use gltf::json::{self, validation::Checked};
+use image::RgbaImage;
+
+use super::{Indices, MeshVertex, PreparedModel, PreparedPrimitive};
+use crate::{
+    gltf::mesh_mode_to_topology,
+    mesh::{MeshDocument, MeshMaterial},
+};
+
+#[derive(Debug, Clone)]
+pub struct SceneNode {
+    pub name: Option<String>,
+    /// Relative to the parent
+    pub transform: Mat4,
+    pub children: Vec<usize>,
+    pub mesh: Option<usize>,
+    /// `EXT_mesh_gpu_instancing` transforms, empty for a single instance
+    pub instances: Vec<Mat4>,
+}
+
+#[derive(Debug)]
+pub struct ScenePrimitive {
+    /// Primitive index in the source mesh
+    pub index: usize,
+    pub vertices: Vec<MeshVertex>,
+    pub indices: Option<Indices>,
+    pub topology: wgpu::PrimitiveTopology,
+    pub material: Option<usize>,
+    /// `KHR_materials_variants` material by variant index
+    pub variant_materials: HashMap<usize, usize>,
+}
+
+#[derive(Debug)]
+pub struct SceneMesh {
+    pub name: Option<String>,
+    /// Primitives without positions are left out
+    pub primitives: Vec<ScenePrimitive>,
+}
+
+/// Encoded PNG, JPEG, WebP or KTX2 image. Images of the source document are referenced and
+/// read from it again on export.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SceneImage {
+    /// Byte range of a buffer of [`Scene::source`]
+    View { buffer: usize, range: Range<usize> },
+    /// Relative to [`Scene::source`], or a `data:` URI
+    Uri(String),
+    /// Generated, or of a document loaded from memory
+    Encoded(Vec<u8>),
+}
+
+#[derive(Debug, Default)]
+pub struct Scene {
+    pub nodes: Vec<SceneNode>,
+    pub meshes: Vec<SceneMesh>,
+    /// Names of `KHR_materials_variants` variants
+    pub variants: Vec<String>,
+    /// Materials, textures and samplers as glTF JSON, indexing each other and `images`
+    pub materials: Vec<json::Material>,
+    pub textures: Vec<json::Texture>,
+    pub samplers: Vec<json::texture::Sampler>,
+    pub images: Vec<SceneImage>,
+    /// Extensions of the source document
+    pub extensions_used: Vec<String>,
+    /// File of the source document
+    pub source: Option<PathBuf>,
+}
+
+impl Scene {
+    /// Takes the vertices of the prepared model, materials are kept as in the source document.
+    pub fn from_prepared(prepared: PreparedModel) -> Self {
+        let PreparedModel {
+            gltf,
+            mut instance_transforms,
+            mut primitives,
+            ..
+        } = prepared;
+        let json = gltf.document.as_json();
+
+        let nodes = gltf
+            .document
+            .nodes()
+            .map(|node| SceneNode {
+                name: node.name().map(Into::into),
+                transform: Mat4::from_cols_array_2d(&node.transform().matrix()),
+                children: node.children().map(|child| child.index()).collect(),
+                mesh: node.mesh().map(|mesh| mesh.index()),
+                instances: instance_transforms
+                    .remove(&node.index())
+                    .unwrap_or_default(),
+            })
+            .collect();
+        let meshes = gltf
+            .document
+            .meshes()
+            .map(|mesh| SceneMesh {
+                name: mesh.name().map(Into::into),
+                primitives: mesh
+                    .primitives()
+                    .filter_map(|primitive| {
+                        let PreparedPrimitive { vertices, indices } =
+                            primitives.remove(&(mesh.index(), primitive.index()))?;
+                        Some(ScenePrimitive {
+                            index: primitive.index(),
+                            vertices,
+                            indices,
+                            topology: mesh_mode_to_topology(primitive.mode()),
+                            material: primitive.material().index(),
+                            variant_materials: variant_materials(&primitive),
+                        })
+                    })
+                    .collect(),
+            })
+            .collect();
+        // Views are bounds checked on import
+        let images = gltf
+            .document
+            .images()
+            .map(|image| match image.source() {
+                gltf::image::Source::View { view, .. } => {
+                    let buffer = view.buffer().index();
+                    let range = view.offset()..view.offset() + view.length();
+                    match gltf.path {
+                        Some(_) => SceneImage::View { buffer, range },
+                        None => SceneImage::Encoded(gltf.buffers[buffer][range].to_vec()),
+                    }
+                }
+                gltf::image::Source::Uri { uri, .. } => SceneImage::Uri(uri.to_owned()),
+            })
+            .collect();
+
+        Self {
+            nodes,
+            meshes,
+            variants: gltf
+                .document
+                .variants()
+                .into_iter()
+                .flatten()
+                .map(|variant| variant.name().to_owned())
+                .collect(),
+            materials: json.materials.clone(),
+            textures: json.textures.clone(),
+            samplers: json.samplers.clone(),
+            images,
+            extensions_used: json.extensions_used.clone(),
+            source: gltf.path,
+        }
+    }
+
+    /// One node per mesh, materials become metallic-roughness with PNG textures.
+    pub fn from_mesh_document(document: MeshDocument) -> Result<Self> {
+        let mut scene = Self::default();
+        for material in &document.materials {
+            scene.push_mesh_material(material)?;
+        }
+        // Same as the default material of the renderer, which isn't metallic unlike glTF's
+        let default_material = document
+            .meshes
+            .iter()
+            .any(|mesh| mesh.material.is_none())
+            .then(|| {
+                scene.materials.push(json::Material {
+                    name: Some("Default".to_owned()),
+                    pbr_metallic_roughness: json::material::PbrMetallicRoughness {
+                        metallic_factor: json::material::StrengthFactor(0.),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                });
+                scene.materials.len() - 1
+            });
+
+        for mesh in document.meshes {
+            let mesh_index = (!mesh.vertices.is_empty()).then(|| {
+                scene.meshes.push(SceneMesh {
+                    name: Some(mesh.name.clone()),
+                    primitives: vec![ScenePrimitive {
+                        index: 0,
+                        vertices: mesh.vertices,
+                        indices: mesh.indices,
+                        topology: mesh.topology,
+                        material: mesh.material.or(default_material),
+                        variant_materials: HashMap::new(),
+                    }],
+                });
+                scene.meshes.len() - 1
+            });
+            scene.nodes.push(SceneNode {
+                name: Some(mesh.name),
+                transform: Mat4::IDENTITY,
+                children: vec![],
+                mesh: mesh_index,
+                instances: vec![],
+            });
+        }
+        Ok(scene)
+    }
+
+    fn push_mesh_material(&mut self, material: &MeshMaterial) -> Result<()> {
+        let mut push_texture = |levels: &Option<Vec<RgbaImage>>| -> Result<_> {
+            let Some(levels) = levels else {
+                return Ok(None);
+            };
+            let mut data = Cursor::new(vec![]);
+            levels[0].write_to(&mut data, image::ImageOutputFormat::Png)?;
+            self.images.push(SceneImage::Encoded(data.into_inner()));
+            self.textures.push(json::Texture {
+                name: None,
+                sampler: None,
+                source: json::Index::new(self.images.len() as u32 - 1),
+                extensions: None,
+                extras: Default::default(),
+            });
+            Ok(Some(json::Index::new(self.textures.len() as u32 - 1)))
+        };
+        let base_color_texture = push_texture(&material.base_color_mips)?;
+        let normal_texture = push_texture(&material.normal_mips)?;
+
+        let [r, g, b] = material.base_color_factor;
+        self.materials.push(json::Material {
+            name: Some(material.name.clone()),
+            alpha_mode: Checked::Valid(material.alpha_mode),
+            pbr_metallic_roughness: json::material::PbrMetallicRoughness {
+                base_color_factor: json::material::PbrBaseColorFactor([r, g, b, 1.]),
+                base_color_texture: base_color_texture.map(|index| json::texture::Info {
+                    index,
+                    tex_coord: 0,
+                    extensions: None,
+                    extras: Default::default(),
+                }),
+                metallic_factor: json::material::StrengthFactor(0.),
+                roughness_factor: json::material::StrengthFactor(material.roughness_factor),
+                ..Default::default()
+            },
+            normal_texture: normal_texture.map(|index| json::material::NormalTexture {
+                index,
+                scale: 1.,
+                tex_coord: 0,
+                extensions: None,
+                extras: Default::default(),
+            }),
+            ..Default::default()
+        });
+        Ok(())
+    }
+
+    /// World matrices of all nodes, indexed by node index.
+    pub fn world_transforms(&self) -> Vec<Mat4> {
+        let mut transforms: Vec<_> = self.nodes.iter().map(|node| node.transform).collect();
+        let mut stack = self.roots();
+        while let Some(node) = stack.pop() {
+            for &child in &self.nodes[node].children {
+                transforms[child] = transforms[node] * self.nodes[child].transform;
+                stack.push(child);
+            }
+        }
+        transforms
+    }
+
+    /// Nodes that aren't a child of another one.
+    pub fn roots(&self) -> Vec<usize> {
+        let mut is_child = vec![false; self.nodes.len()];
+        for &child in self.nodes.iter().flat_map(|node| &node.children) {
+            is_child[child] = true;
+        }
+        (0..self.nodes.len())
+            .filter(|&node| !is_child[node])
+            .collect()
+    }
+}
+
+/// `KHR_materials_variants` mappings of the primitive, material by variant index.
+pub fn variant_materials(primitive: &gltf::Primitive) -> HashMap<usize, usize> {
+    primitive
+        .mappings()
+        .filter_map(|mapping| Some((mapping.material().index()?, mapping.variants())))
+        .flat_map(|(material, variants)| {
+            variants
+                .iter()
+                .map(move |&variant| (variant as usize, material))
+        })
+        .collect()
+}
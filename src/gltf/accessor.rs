@@ -0,0 +1,363 @@
+use std::{borrow::Cow, iter::zip, mem::size_of};
+
+use bytemuck::Pod;
+use color_eyre::{
+    eyre::{bail, ContextCompat},
+    Result,
+};
+use gltf::accessor::{sparse::IndexType, DataType};
+
+use super::{component_count_of_type, component_type_to_index_format, GltfDocument};
+use crate::app::Indices;
+
+/// Accessor component that can be widened to `f32`.
+trait Component: Pod {
+    fn to_f32(self, normalized: bool) -> f32;
+}
+
+macro_rules! impl_component {
+    ($ty:ty, $normalize:expr) => {
+        impl Component for $ty {
+            fn to_f32(self, normalized: bool) -> f32 {
+                if normalized {
+                    $normalize(self)
+                } else {
+                    self as f32
+                }
+            }
+        }
+    };
+}
+
+impl_component!(i8, |c: i8| (c as f32 / 127.).max(-1.));
+impl_component!(u8, |c: u8| c as f32 / 255.);
+impl_component!(i16, |c: i16| (c as f32 / 32767.).max(-1.));
+impl_component!(u16, |c: u16| c as f32 / 65535.);
+impl_component!(u32, |c: u32| c as f32 / u32::MAX as f32);
+impl_component!(f32, |c: f32| c);
+
+impl GltfDocument {
+    /// Tightly packed elements of the accessor. Accessors without a buffer view
+    /// start zeroed, then sparse values are substituted at their indices.
+    pub fn data_of_accessor<'a>(&'a self, accessor: &gltf::Accessor) -> Result<Cow<'a, [u8]>> {
+        let element_size = accessor.size();
+        let count = accessor.count();
+        let mut data = match accessor.view() {
+            Some(view) => {
+                let stride = view.stride().unwrap_or(element_size);
+                let len = match count {
+                    0 => 0,
+                    count => (count - 1) * stride + element_size,
+                };
+                let data = self
+                    .data_of_view(&view)?
+                    .get(accessor.offset()..accessor.offset() + len)
+                    .context("Invalid GLTF: Accessor out of buffer view bounds.")?;
+                if stride == element_size {
+                    Cow::Borrowed(data)
+                } else {
+                    data.chunks(stride)
+                        .flat_map(|element| &element[..element_size])
+                        .copied()
+                        .collect()
+                }
+            }
+            None => Cow::Owned(vec![0; count * element_size]),
+        };
+
+        if let Some(sparse) = accessor.sparse() {
+            let indices = sparse.indices();
+            let index_size = match indices.index_type() {
+                IndexType::U8 => 1,
+                IndexType::U16 => 2,
+                IndexType::U32 => 4,
+            };
+            let index_data = self
+                .data_of_view(&indices.view())?
+                .get(indices.offset()..indices.offset() + sparse.count() * index_size)
+                .context("Invalid GLTF: Sparse indices out of buffer view bounds.")?;
+            let values = sparse.values();
+            let value_data = self
+                .data_of_view(&values.view())?
+                .get(values.offset()..values.offset() + sparse.count() * element_size)
+                .context("Invalid GLTF: Sparse values out of buffer view bounds.")?;
+
+            let data = data.to_mut();
+            for (index, value) in zip(
+                index_data.chunks_exact(index_size),
+                value_data.chunks_exact(element_size),
+            ) {
+                let index = match indices.index_type() {
+                    IndexType::U8 => index[0] as usize,
+                    IndexType::U16 => bytemuck::pod_read_unaligned::<u16>(index) as usize,
+                    IndexType::U32 => bytemuck::pod_read_unaligned::<u32>(index) as usize,
+                };
+                if index >= count {
+                    bail!("Invalid GLTF: Sparse index {index} out of bounds of {count} elements.");
+                }
+                data[index * element_size..(index + 1) * element_size].copy_from_slice(value);
+            }
+        }
+        Ok(data)
+    }
+
+    fn data_of_view(&self, view: &gltf::buffer::View) -> Result<&[u8]> {
+        self.buffers[view.buffer().index()]
+            .get(view.offset()..view.offset() + view.length())
+            .context("Invalid GLTF: Buffer view out of buffer bounds.")
+    }
+
+    /// Reads `N` component accessor as floats, dequantizing integer components
+    /// as allowed by `KHR_mesh_quantization`.
+    ///
+    /// Quantized data is expanded on the CPU and uploaded as `f32`, every primitive shares
+    /// the `MeshVertex` layout of the vertex arena.
+    pub fn read_f32<const N: usize>(&self, accessor: &gltf::Accessor) -> Result<Vec<[f32; N]>> {
+        let components = component_count_of_type(accessor.dimensions());
+        if components != N {
+            bail!("Invalid GLTF: Expected {N} components, accessor has {components}.");
+        }
+        match accessor.data_type() {
+            DataType::I8 => self.read_components::<i8, N>(accessor),
+            DataType::U8 => self.read_components::<u8, N>(accessor),
+            DataType::I16 => self.read_components::<i16, N>(accessor),
+            DataType::U16 => self.read_components::<u16, N>(accessor),
+            DataType::U32 => self.read_components::<u32, N>(accessor),
+            DataType::F32 => self.read_components::<f32, N>(accessor),
+        }
+    }
+
+    pub fn read_attribute<const N: usize>(
+        &self,
+        primitive: &gltf::Primitive,
+        semantic: gltf::Semantic,
+    ) -> Result<Option<Vec<[f32; N]>>> {
+        primitive
+            .get(&semantic)
+            .map(|accessor| self.read_f32(&accessor))
+            .transpose()
+    }
+
+    /// `COLOR_0` as RGBA, RGB colors get opaque alpha.
+    pub fn read_colors(&self, primitive: &gltf::Primitive) -> Result<Option<Vec<[f32; 4]>>> {
+        let Some(accessor) = primitive.get(&gltf::Semantic::Colors(0)) else {
+            return Ok(None);
+        };
+        let colors = match accessor.dimensions() {
+            gltf::accessor::Dimensions::Vec3 => self
+                .read_f32::<3>(&accessor)?
+                .into_iter()
+                .map(|[r, g, b]| [r, g, b, 1.])
+                .collect(),
+            _ => self.read_f32::<4>(&accessor)?,
+        };
+        Ok(Some(colors))
+    }
+
+    pub fn read_indices(&self, accessor: &gltf::Accessor) -> Result<Indices> {
+        let format = component_type_to_index_format(accessor.data_type())?;
+        let data = self.data_of_accessor(accessor)?;
+        let indices = match (accessor.data_type(), format) {
+            (DataType::U8, _) => Indices::U16(data.iter().map(|&index| index as u16).collect()),
+            (_, wgpu::IndexFormat::Uint16) => Indices::U16(
+                data.chunks_exact(2)
+                    .map(bytemuck::pod_read_unaligned::<u16>)
+                    .collect(),
+            ),
+            (_, wgpu::IndexFormat::Uint32) => Indices::U32(
+                data.chunks_exact(4)
+                    .map(bytemuck::pod_read_unaligned::<u32>)
+                    .collect(),
+            ),
+        };
+        Ok(indices)
+    }
+
+    fn read_components<T: Component, const N: usize>(
+        &self,
+        accessor: &gltf::Accessor,
+    ) -> Result<Vec<[f32; N]>> {
+        let normalized = accessor.normalized();
+        let data = self.data_of_accessor(accessor)?;
+        Ok(data
+            .chunks_exact(size_of::<T>() * N)
+            .map(|element| {
+                std::array::from_fn(|c| {
+                    bytemuck::pod_read_unaligned::<T>(
+                        &element[c * size_of::<T>()..(c + 1) * size_of::<T>()],
+                    )
+                    .to_f32(normalized)
+                })
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn document(json: &str, buffer: Vec<u8>) -> GltfDocument {
+        GltfDocument {
+            document: gltf::Gltf::from_slice(json.as_bytes()).unwrap().document,
+            buffers: vec![gltf::buffer::Data(buffer)],
+            images: vec![],
+            path: None,
+        }
+    }
+
+    fn bytes<T: Pod>(data: &[T]) -> Vec<u8> {
+        bytemuck::cast_slice(data).to_vec()
+    }
+
+    #[test]
+    fn sparse_without_buffer_view() {
+        let mut buffer = bytes(&[1u16, 3]);
+        buffer.extend(bytes(&[[1f32, 2., 3.], [4., 5., 6.]]));
+        let gltf = document(
+            r#"{
+                "asset": { "version": "2.0" },
+                "buffers": [{ "byteLength": 28 }],
+                "bufferViews": [
+                    { "buffer": 0, "byteOffset": 0, "byteLength": 4 },
+                    { "buffer": 0, "byteOffset": 4, "byteLength": 24 }
+                ],
+                "accessors": [{
+                    "componentType": 5126,
+                    "count": 4,
+                    "type": "VEC3",
+                    "sparse": {
+                        "count": 2,
+                        "indices": { "bufferView": 0, "componentType": 5123 },
+                        "values": { "bufferView": 1 }
+                    }
+                }]
+            }"#,
+            buffer,
+        );
+        let accessor = gltf.document.accessors().next().unwrap();
+        assert_eq!(
+            gltf.read_f32::<3>(&accessor).unwrap(),
+            [[0.; 3], [1., 2., 3.], [0.; 3], [4., 5., 6.]]
+        );
+    }
+
+    #[test]
+    fn sparse_over_strided_view() {
+        let mut buffer = bytes(&[[1f32, 1., 1., 0.], [2., 2., 2., 0.], [3., 3., 3., 0.]]);
+        buffer.extend([2u8, 0, 0, 0]);
+        buffer.extend(bytes(&[9f32, 8., 7.]));
+        let gltf = document(
+            r#"{
+                "asset": { "version": "2.0" },
+                "buffers": [{ "byteLength": 64 }],
+                "bufferViews": [
+                    { "buffer": 0, "byteOffset": 0, "byteLength": 48, "byteStride": 16 },
+                    { "buffer": 0, "byteOffset": 48, "byteLength": 1 },
+                    { "buffer": 0, "byteOffset": 52, "byteLength": 12 }
+                ],
+                "accessors": [{
+                    "bufferView": 0,
+                    "componentType": 5126,
+                    "count": 3,
+                    "type": "VEC3",
+                    "sparse": {
+                        "count": 1,
+                        "indices": { "bufferView": 1, "componentType": 5121 },
+                        "values": { "bufferView": 2 }
+                    }
+                }]
+            }"#,
+            buffer,
+        );
+        let accessor = gltf.document.accessors().next().unwrap();
+        assert_eq!(gltf.data_of_accessor(&accessor).unwrap().len(), 36);
+        assert_eq!(
+            gltf.read_f32::<3>(&accessor).unwrap(),
+            [[1.; 3], [2.; 3], [9., 8., 7.]]
+        );
+    }
+
+    #[test]
+    fn sparse_index_out_of_bounds() {
+        let mut buffer = bytes(&[5u32]);
+        buffer.extend(bytes(&[1f32]));
+        let gltf = document(
+            r#"{
+                "asset": { "version": "2.0" },
+                "buffers": [{ "byteLength": 8 }],
+                "bufferViews": [
+                    { "buffer": 0, "byteOffset": 0, "byteLength": 4 },
+                    { "buffer": 0, "byteOffset": 4, "byteLength": 4 }
+                ],
+                "accessors": [{
+                    "componentType": 5126,
+                    "count": 2,
+                    "type": "SCALAR",
+                    "sparse": {
+                        "count": 1,
+                        "indices": { "bufferView": 0, "componentType": 5125 },
+                        "values": { "bufferView": 1 }
+                    }
+                }]
+            }"#,
+            buffer,
+        );
+        let accessor = gltf.document.accessors().next().unwrap();
+        assert!(gltf.data_of_accessor(&accessor).is_err());
+    }
+
+    #[test]
+    fn normalized_components() {
+        let gltf = document(
+            r#"{
+                "asset": { "version": "2.0" },
+                "buffers": [{ "byteLength": 4 }],
+                "bufferViews": [{ "buffer": 0, "byteOffset": 0, "byteLength": 4 }],
+                "accessors": [{
+                    "bufferView": 0,
+                    "componentType": 5120,
+                    "normalized": true,
+                    "count": 2,
+                    "type": "VEC2"
+                }]
+            }"#,
+            bytes(&[127i8, -128, 0, -127]),
+        );
+        let accessor = gltf.document.accessors().next().unwrap();
+        assert_eq!(
+            gltf.read_f32::<2>(&accessor).unwrap(),
+            [[1., -1.], [0., -1.]]
+        );
+    }
+
+    #[test]
+    fn indices_keep_their_width() {
+        let gltf = document(
+            r#"{
+                "asset": { "version": "2.0" },
+                "buffers": [{ "byteLength": 12 }],
+                "bufferViews": [{ "buffer": 0, "byteOffset": 0, "byteLength": 12 }],
+                "accessors": [
+                    { "bufferView": 0, "componentType": 5121, "count": 3, "type": "SCALAR" },
+                    { "bufferView": 0, "componentType": 5123, "count": 3, "type": "SCALAR" },
+                    { "bufferView": 0, "componentType": 5125, "count": 3, "type": "SCALAR" }
+                ]
+            }"#,
+            bytes(&[1u32, 2, 3]),
+        );
+        let indices: Vec<_> = gltf
+            .document
+            .accessors()
+            .map(|accessor| gltf.read_indices(&accessor).unwrap())
+            .collect();
+        assert_eq!(
+            indices,
+            [
+                Indices::U16(vec![1, 0, 0]),
+                Indices::U16(vec![1, 0, 2]),
+                Indices::U32(vec![1, 2, 3]),
+            ]
+        );
+    }
+}
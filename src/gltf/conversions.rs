@@ -9,11 +9,12 @@ use gltf::{
 use image::{buffer::ConvertBuffer, ImageBuffer};
 use wgpu::{FilterMode, PrimitiveTopology, TextureFormat};
 
-pub fn component_type_to_index_format(ty: gltf::accessor::DataType) -> wgpu::IndexFormat {
+/// 8-bit indices have no wgpu format and are widened to 16-bit.
+pub fn component_type_to_index_format(ty: gltf::accessor::DataType) -> Result<wgpu::IndexFormat> {
     match ty {
-        DataType::U16 => wgpu::IndexFormat::Uint16,
-        DataType::U32 => wgpu::IndexFormat::Uint32,
-        _ => panic!("Unsupported index format!"),
+        DataType::U8 | DataType::U16 => Ok(wgpu::IndexFormat::Uint16),
+        DataType::U32 => Ok(wgpu::IndexFormat::Uint32),
+        ty => Err(eyre!("Invalid GLTF: Unsupported index type {ty:?}.")),
     }
 }
 
@@ -138,6 +139,18 @@ pub fn mesh_mode_to_topology(mode: gltf::mesh::Mode) -> wgpu::PrimitiveTopology
     }
 }
 
+pub fn topology_to_mesh_mode(topology: wgpu::PrimitiveTopology) -> gltf::mesh::Mode {
+    use gltf::mesh::Mode;
+    use PrimitiveTopology::*;
+    match topology {
+        TriangleList => Mode::Triangles,
+        TriangleStrip => Mode::TriangleStrip,
+        LineList => Mode::Lines,
+        LineStrip => Mode::LineStrip,
+        PointList => Mode::Points,
+    }
+}
+
 pub fn wrappping_to_address_mode(mode: gltf::texture::WrappingMode) -> wgpu::AddressMode {
     use gltf::texture::WrappingMode;
     use wgpu::AddressMode::*;
@@ -0,0 +1,678 @@
+//! Writes a [`Scene`] as glTF or GLB. Geometry becomes interleaved float attributes with
+//! indices at their loaded width, materials, textures and samplers are written as retained.
+
+use std::{borrow::Cow, collections::BTreeMap, mem::offset_of, path::Path};
+
+use color_eyre::{
+    eyre::{bail, Context, ContextCompat},
+    Result,
+};
+use glam::Mat4;
+use gltf::json::{
+    self,
+    accessor::{ComponentType, GenericComponentType, Type},
+    buffer::Target,
+    validation::Checked,
+    Index,
+};
+
+use super::{
+    resolver::read_uri, topology_to_mesh_mode, FileResolver, GltfDocument, Ktx2, NoResolver,
+    TEXTURE_SOURCE_EXTENSIONS,
+};
+use crate::app::{Indices, MeshVertex, Scene, SceneImage, SceneMesh, SceneNode};
+
+/// Extensions of the source document that don't apply to the written geometry,
+/// the ones still in use get added back.
+const GEOMETRY_EXTENSIONS: &[&str] = &[
+    "EXT_mesh_gpu_instancing",
+    "EXT_meshopt_compression",
+    "KHR_draco_mesh_compression",
+    "KHR_materials_variants",
+    "KHR_mesh_quantization",
+];
+
+/// Picks GLB or glTF with a `.bin` next to it by file extension. Primitives get the material
+/// of the `variant` shown in the viewer, the variant mappings are kept.
+pub fn export(scene: &Scene, variant: Option<usize>, path: impl AsRef<Path>) -> Result<()> {
+    let path = path.as_ref();
+    let is_glb = path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("glb"));
+    if is_glb {
+        return std::fs::write(path, to_glb(scene, variant)?)
+            .with_context(|| format!("Failed to write {path:?}"));
+    }
+
+    let (mut root, bin) = to_json(scene, variant)?;
+    if let Some(buffer) = root.buffers.first_mut() {
+        let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+        let bin_path = path.with_file_name(format!("{stem}.bin"));
+        buffer.uri = Some(format!("{stem}.bin"));
+        std::fs::write(&bin_path, bin).with_context(|| format!("Failed to write {bin_path:?}"))?;
+    }
+    let json = json::serialize::to_vec_pretty(&root)?;
+    std::fs::write(path, json).with_context(|| format!("Failed to write {path:?}"))
+}
+
+pub fn to_glb(scene: &Scene, variant: Option<usize>) -> Result<Vec<u8>> {
+    let (root, bin) = to_json(scene, variant)?;
+    let glb = gltf::binary::Glb {
+        // Recomputed on write
+        header: gltf::binary::Header {
+            magic: *b"glTF",
+            version: 2,
+            length: 0,
+        },
+        json: Cow::Owned(json::serialize::to_vec(&root)?),
+        bin: (!bin.is_empty()).then_some(Cow::Owned(bin)),
+    };
+    Ok(glb.to_vec()?)
+}
+
+/// Document with a single buffer without URI and the contents of that buffer.
+pub fn to_json(scene: &Scene, variant: Option<usize>) -> Result<(json::Root, Vec<u8>)> {
+    let mut builder = Builder {
+        variant,
+        ..Default::default()
+    };
+    let root = &mut builder.root;
+    root.asset.generator = Some("wgltf".to_owned());
+    root.materials = scene.materials.clone();
+    root.textures = scene.textures.clone();
+    root.samplers = scene.samplers.clone();
+    root.extensions_used = scene
+        .extensions_used
+        .iter()
+        .filter(|extension| !GEOMETRY_EXTENSIONS.contains(&extension.as_str()))
+        .cloned()
+        .collect();
+    // The texture loses its fallback when the core source is the extension image
+    root.extensions_required = TEXTURE_SOURCE_EXTENSIONS
+        .iter()
+        .filter(|&&extension| {
+            scene.textures.iter().any(|texture| {
+                texture
+                    .extensions
+                    .as_ref()
+                    .and_then(|extensions| extensions.others.get(extension)?.get("source"))
+                    .and_then(json::Value::as_u64)
+                    == Some(texture.source.value() as u64)
+            })
+        })
+        .map(|&extension| extension.to_owned())
+        .collect();
+
+    let mut buffers = None;
+    for image in &scene.images {
+        let data = read_image(scene, image, &mut buffers)?;
+        let mime_type = image_mime_type(&data)?;
+        let buffer_view = builder.view(&data, None);
+        builder.root.push(json::Image {
+            buffer_view: Some(buffer_view),
+            mime_type: Some(json::image::MimeType(mime_type.to_owned())),
+            name: None,
+            uri: None,
+            extensions: None,
+            extras: Default::default(),
+        });
+    }
+
+    if !scene.variants.is_empty() {
+        builder.use_extension("KHR_materials_variants");
+        let variants = scene
+            .variants
+            .iter()
+            .map(
+                |name| json::extensions::scene::khr_materials_variants::Variant {
+                    name: name.clone(),
+                },
+            )
+            .collect();
+        builder.root.extensions = Some(json::extensions::root::Root {
+            khr_materials_variants: Some(json::extensions::root::KhrMaterialsVariants { variants }),
+            ..Default::default()
+        });
+    }
+
+    // Meshes without primitives aren't valid, their nodes are written without them
+    let mut meshes = vec![];
+    for mesh in &scene.meshes {
+        let index = (!mesh.primitives.is_empty()).then(|| builder.mesh(mesh));
+        meshes.push(index);
+    }
+    for node in &scene.nodes {
+        let json_node = builder.node(node, node.mesh.and_then(|mesh| meshes[mesh]));
+        builder.root.push(json_node);
+    }
+    let nodes: Vec<_> = scene
+        .roots()
+        .into_iter()
+        .map(|node| Index::new(node as u32))
+        .collect();
+    // Scenes can't be empty
+    if !nodes.is_empty() {
+        let scene_index = builder.root.push(json::Scene {
+            extensions: None,
+            extras: Default::default(),
+            name: None,
+            nodes,
+        });
+        builder.root.scene = Some(scene_index);
+    }
+
+    let Builder {
+        mut root, mut bin, ..
+    } = builder;
+    if !bin.is_empty() {
+        bin.resize(bin.len().next_multiple_of(4), 0);
+        root.push(json::Buffer {
+            byte_length: bin.len().into(),
+            name: None,
+            uri: None,
+            extensions: None,
+            extras: Default::default(),
+        });
+    }
+    Ok((root, bin))
+}
+
+/// Encoded bytes of the image. Views are read from the buffers of the source document,
+/// which are loaded on first use.
+fn read_image<'a>(
+    scene: &Scene,
+    image: &'a SceneImage,
+    buffers: &'a mut Option<Vec<gltf::buffer::Data>>,
+) -> Result<Cow<'a, [u8]>> {
+    let source = scene.source.as_deref();
+    match image {
+        SceneImage::Encoded(data) => Ok(Cow::Borrowed(data)),
+        SceneImage::Uri(uri) => {
+            let data = match source.and_then(Path::parent) {
+                Some(base) => read_uri(&FileResolver::new(base), uri),
+                None => read_uri(&NoResolver, uri),
+            };
+            Ok(Cow::Owned(data.with_context(|| {
+                format!("Failed to read image {uri:?}")
+            })?))
+        }
+        SceneImage::View { buffer, range } => {
+            let source = source.context("Image view without a source document.")?;
+            if buffers.is_none() {
+                let data = GltfDocument::read_buffers(source)
+                    .with_context(|| format!("Failed to read {source:?}"))?;
+                *buffers = Some(data);
+            }
+            let buffers: &'a Option<_> = buffers;
+            let data = buffers
+                .as_deref()
+                .and_then(|buffers| buffers.get(*buffer)?.get(range.clone()))
+                .with_context(|| format!("Image view out of bounds, {source:?} changed."))?;
+            Ok(Cow::Borrowed(data))
+        }
+    }
+}
+
+fn image_mime_type(data: &[u8]) -> Result<&'static str> {
+    if Ktx2::is_ktx2(data) {
+        return Ok("image/ktx2");
+    }
+    let mime_type = match image::guess_format(data)? {
+        image::ImageFormat::Png => "image/png",
+        image::ImageFormat::Jpeg => "image/jpeg",
+        image::ImageFormat::WebP => "image/webp",
+        format => bail!("Can't export {format:?} images."),
+    };
+    Ok(mime_type)
+}
+
+#[derive(Default)]
+struct Builder {
+    root: json::Root,
+    bin: Vec<u8>,
+    /// Active material variant
+    variant: Option<usize>,
+}
+
+impl Builder {
+    fn use_extension(&mut self, extension: &str) {
+        if !self
+            .root
+            .extensions_used
+            .iter()
+            .any(|used| used == extension)
+        {
+            self.root.extensions_used.push(extension.to_owned());
+        }
+    }
+
+    fn view(&mut self, data: &[u8], target: Option<Target>) -> Index<json::buffer::View> {
+        self.bin.resize(self.bin.len().next_multiple_of(4), 0);
+        let offset = self.bin.len();
+        self.bin.extend_from_slice(data);
+        self.root.push(json::buffer::View {
+            buffer: Index::new(0),
+            byte_length: data.len().into(),
+            byte_offset: Some(offset.into()),
+            byte_stride: (target == Some(Target::ArrayBuffer))
+                .then_some(json::buffer::Stride(std::mem::size_of::<MeshVertex>())),
+            name: None,
+            target: target.map(Checked::Valid),
+            extensions: None,
+            extras: Default::default(),
+        })
+    }
+
+    fn accessor(
+        &mut self,
+        view: Index<json::buffer::View>,
+        offset: usize,
+        count: usize,
+        component_type: ComponentType,
+        type_: Type,
+    ) -> Index<json::Accessor> {
+        self.root.push(json::Accessor {
+            buffer_view: Some(view),
+            byte_offset: Some(offset.into()),
+            count: count.into(),
+            component_type: Checked::Valid(GenericComponentType(component_type)),
+            extensions: None,
+            extras: Default::default(),
+            type_: Checked::Valid(type_),
+            min: None,
+            max: None,
+            name: None,
+            normalized: false,
+            sparse: None,
+        })
+    }
+
+    /// Tightly packed floats, used for `EXT_mesh_gpu_instancing` attributes.
+    fn float_accessor<T: bytemuck::Pod>(
+        &mut self,
+        data: &[T],
+        type_: Type,
+    ) -> Index<json::Accessor> {
+        let view = self.view(bytemuck::cast_slice(data), None);
+        self.accessor(view, 0, data.len(), ComponentType::F32, type_)
+    }
+
+    fn mesh(&mut self, mesh: &SceneMesh) -> Index<json::Mesh> {
+        let mut primitives = vec![];
+        for primitive in &mesh.primitives {
+            let vertices = &primitive.vertices;
+            let view = self.view(bytemuck::cast_slice(vertices), Some(Target::ArrayBuffer));
+            let count = vertices.len();
+            let mut attributes = BTreeMap::new();
+
+            let position = self.accessor(view, 0, count, ComponentType::F32, Type::Vec3);
+            let (min, max) =
+                vertices
+                    .iter()
+                    .fold(([f32::MAX; 3], [f32::MIN; 3]), |(min, max), vertex| {
+                        (
+                            std::array::from_fn(|i| min[i].min(vertex.position[i])),
+                            std::array::from_fn(|i| max[i].max(vertex.position[i])),
+                        )
+                    });
+            let accessor = &mut self.root.accessors[position.value()];
+            accessor.min = Some(json::Value::from(min.to_vec()));
+            accessor.max = Some(json::Value::from(max.to_vec()));
+            attributes.insert(Checked::Valid(json::mesh::Semantic::Positions), position);
+
+            // Attributes the importer defaults to are left out
+            if vertices.iter().any(|vertex| vertex.normal != [0.; 3]) {
+                let normal = self.accessor(
+                    view,
+                    offset_of!(MeshVertex, normal),
+                    count,
+                    ComponentType::F32,
+                    Type::Vec3,
+                );
+                attributes.insert(Checked::Valid(json::mesh::Semantic::Normals), normal);
+            }
+            if vertices.iter().any(|vertex| vertex.tex_coord != [0.; 2]) {
+                let tex_coord = self.accessor(
+                    view,
+                    offset_of!(MeshVertex, tex_coord),
+                    count,
+                    ComponentType::F32,
+                    Type::Vec2,
+                );
+                attributes.insert(
+                    Checked::Valid(json::mesh::Semantic::TexCoords(0)),
+                    tex_coord,
+                );
+            }
+            if vertices.iter().any(|vertex| vertex.color != [1.; 4]) {
+                let color = self.accessor(
+                    view,
+                    offset_of!(MeshVertex, color),
+                    count,
+                    ComponentType::F32,
+                    Type::Vec4,
+                );
+                attributes.insert(Checked::Valid(json::mesh::Semantic::Colors(0)), color);
+            }
+
+            let indices = primitive.indices.as_ref().map(|indices| {
+                let view = self.view(indices.as_bytes(), Some(Target::ElementArrayBuffer));
+                let component_type = match indices {
+                    Indices::U16(_) => ComponentType::U16,
+                    Indices::U32(_) => ComponentType::U32,
+                };
+                self.accessor(view, 0, indices.len(), component_type, Type::Scalar)
+            });
+
+            let mut mappings: BTreeMap<usize, Vec<u32>> = BTreeMap::new();
+            for (&variant, &material) in &primitive.variant_materials {
+                mappings.entry(material).or_default().push(variant as u32);
+            }
+            let extensions = (!mappings.is_empty()).then(|| json::extensions::mesh::Primitive {
+                khr_materials_variants: Some(json::extensions::mesh::KhrMaterialsVariants {
+                    mappings: mappings
+                        .into_iter()
+                        .map(|(material, mut variants)| {
+                            variants.sort_unstable();
+                            json::extensions::mesh::Mapping {
+                                material: material as u32,
+                                variants,
+                            }
+                        })
+                        .collect(),
+                }),
+                ..Default::default()
+            });
+
+            primitives.push(json::mesh::Primitive {
+                attributes,
+                extensions,
+                extras: Default::default(),
+                indices,
+                material: self
+                    .variant
+                    .and_then(|variant| primitive.variant_materials.get(&variant).copied())
+                    .or(primitive.material)
+                    .map(|material| Index::new(material as u32)),
+                mode: Checked::Valid(topology_to_mesh_mode(primitive.topology)),
+                targets: None,
+            });
+        }
+        self.root.push(json::Mesh {
+            extensions: None,
+            extras: Default::default(),
+            name: mesh.name.clone(),
+            primitives,
+            weights: None,
+        })
+    }
+
+    fn node(&mut self, node: &SceneNode, mesh: Option<Index<json::Mesh>>) -> json::Node {
+        let mut extensions = None;
+        if !node.instances.is_empty() {
+            self.use_extension("EXT_mesh_gpu_instancing");
+            let decomposed: Vec<_> = node
+                .instances
+                .iter()
+                .map(Mat4::to_scale_rotation_translation)
+                .collect();
+            let translations: Vec<_> = decomposed.iter().map(|(_, _, t)| t.to_array()).collect();
+            let rotations: Vec<_> = decomposed.iter().map(|(_, r, _)| r.to_array()).collect();
+            let scales: Vec<_> = decomposed.iter().map(|(s, _, _)| s.to_array()).collect();
+            let attributes = json::Value::from_iter([
+                (
+                    "TRANSLATION",
+                    self.float_accessor(&translations, Type::Vec3).value(),
+                ),
+                (
+                    "ROTATION",
+                    self.float_accessor(&rotations, Type::Vec4).value(),
+                ),
+                ("SCALE", self.float_accessor(&scales, Type::Vec3).value()),
+            ]);
+            let mut node_extensions = json::extensions::scene::Node::default();
+            node_extensions.others.insert(
+                "EXT_mesh_gpu_instancing".to_owned(),
+                json::Value::from_iter([("attributes", attributes)]),
+            );
+            extensions = Some(node_extensions);
+        }
+
+        json::Node {
+            name: node.name.clone(),
+            children: (!node.children.is_empty()).then(|| {
+                node.children
+                    .iter()
+                    .map(|&child| Index::new(child as u32))
+                    .collect()
+            }),
+            mesh,
+            matrix: (node.transform != Mat4::IDENTITY).then(|| node.transform.to_cols_array()),
+            extensions,
+            ..Default::default()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, path::PathBuf};
+
+    use super::*;
+    use crate::{
+        app::{PreparedModel, ScenePrimitive},
+        gltf::LoadProgress,
+        mesh::MeshDocument,
+    };
+
+    /// Variants of the bundled sample models the importer reads, Draco isn't supported.
+    const SAMPLE_VARIANTS: &[&str] = &[
+        "glTF",
+        "glTF-Binary",
+        "glTF-Embedded",
+        "glTF-KTX-BasisU",
+        "glTF-Meshopt",
+        "glTF-Quantized",
+    ];
+
+    /// Sample models with all their external files bundled.
+    fn sample_models() -> Vec<PathBuf> {
+        let mut paths = vec![];
+        for model in std::fs::read_dir("assets/glTF-Sample-Models/2.0").unwrap() {
+            let model = model.unwrap().path();
+            for variant in SAMPLE_VARIANTS {
+                let Ok(files) = std::fs::read_dir(model.join(variant)) else {
+                    continue;
+                };
+                paths.extend(
+                    files
+                        .map(|file| file.unwrap().path())
+                        .filter(|path| is_complete_sample(path)),
+                );
+            }
+        }
+        paths.sort();
+        paths
+    }
+
+    fn is_complete_sample(path: &Path) -> bool {
+        let is_gltf = path
+            .extension()
+            .is_some_and(|extension| extension == "gltf" || extension == "glb");
+        if !is_gltf {
+            return false;
+        }
+        let gltf = gltf::Gltf::open(path).unwrap();
+        let buffer_uris = gltf.buffers().filter_map(|buffer| match buffer.source() {
+            gltf::buffer::Source::Uri(uri) => Some(uri),
+            gltf::buffer::Source::Bin => None,
+        });
+        let image_uris = gltf.images().filter_map(|image| match image.source() {
+            gltf::image::Source::Uri { uri, .. } => Some(uri),
+            gltf::image::Source::View { .. } => None,
+        });
+        buffer_uris
+            .chain(image_uris)
+            .all(|uri| uri.starts_with("data:") || path.with_file_name(uri).exists())
+    }
+
+    fn import(path: impl AsRef<Path>) -> Scene {
+        let gltf = GltfDocument::import(path).unwrap();
+        Scene::from_prepared(PreparedModel::new(gltf, &LoadProgress::default()).unwrap())
+    }
+
+    fn image_data(scene: &Scene) -> Vec<Vec<u8>> {
+        let mut buffers = None;
+        scene
+            .images
+            .iter()
+            .map(|image| read_image(scene, image, &mut buffers).unwrap().into_owned())
+            .collect()
+    }
+
+    fn reimport(scene: &Scene) -> Scene {
+        reimport_variant(scene, None)
+    }
+
+    fn reimport_variant(scene: &Scene, variant: Option<usize>) -> Scene {
+        let glb = to_glb(scene, variant).unwrap();
+        let gltf = GltfDocument::from_slice(&glb, &NoResolver).unwrap();
+        Scene::from_prepared(PreparedModel::new(gltf, &LoadProgress::default()).unwrap())
+    }
+
+    fn assert_same_geometry(a: &Scene, b: &Scene) {
+        assert_eq!(a.world_transforms(), b.world_transforms());
+        for (a, b) in a.nodes.iter().zip(&b.nodes) {
+            assert_eq!(a.mesh, b.mesh);
+            assert_eq!(a.instances.len(), b.instances.len());
+            for (a, b) in a.instances.iter().zip(&b.instances) {
+                assert!(a.abs_diff_eq(*b, 1e-6));
+            }
+        }
+        assert_eq!(a.meshes.len(), b.meshes.len());
+        for (a, b) in a.meshes.iter().zip(&b.meshes) {
+            assert_eq!(a.primitives.len(), b.primitives.len());
+            for (a, b) in a.primitives.iter().zip(&b.primitives) {
+                let bytes =
+                    |vertices: &[MeshVertex]| bytemuck::cast_slice::<_, u8>(vertices).to_vec();
+                assert_eq!(bytes(&a.vertices), bytes(&b.vertices));
+                assert_eq!(a.indices, b.indices);
+                assert_eq!(a.topology, b.topology);
+                assert_eq!(a.material, b.material);
+                assert_eq!(a.variant_materials, b.variant_materials);
+            }
+        }
+        assert_eq!(a.materials.len(), b.materials.len());
+        assert_eq!(image_data(a), image_data(b));
+    }
+
+    #[test]
+    fn round_trip_sample_models() {
+        let paths = sample_models();
+        assert!(!paths.is_empty());
+        for path in paths {
+            let scene = import(&path);
+            // Images of imported files are only referenced
+            assert!(
+                !scene
+                    .images
+                    .iter()
+                    .any(|image| matches!(image, SceneImage::Encoded(_))),
+                "{path:?}"
+            );
+            assert_same_geometry(&scene, &reimport(&scene));
+        }
+    }
+
+    #[test]
+    fn round_trip_external_files() {
+        let dir = std::env::temp_dir().join("wgltf_export_round_trip_external_files");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::copy("assets/cube/cube-normal.png", dir.join("normal.png")).unwrap();
+        std::fs::write(
+            dir.join("image.gltf"),
+            r#"{
+                "asset": { "version": "2.0" },
+                "images": [{ "uri": "normal.png" }],
+                "textures": [{ "source": 0 }]
+            }"#,
+        )
+        .unwrap();
+        let scene = import(dir.join("image.gltf"));
+        assert_eq!(scene.images, [SceneImage::Uri("normal.png".to_owned())]);
+        assert_same_geometry(&scene, &reimport(&scene));
+
+        // Images become views of the `.bin` next to the written document
+        let path = dir.join("helmet.gltf");
+        export(
+            &import("assets/glTF-Sample-Models/2.0/DamagedHelmet/glTF-Binary/DamagedHelmet.glb"),
+            None,
+            &path,
+        )
+        .unwrap();
+        let scene = import(&path);
+        assert!(matches!(scene.images[0], SceneImage::View { .. }));
+        assert_same_geometry(&scene, &reimport(&scene));
+    }
+
+    #[test]
+    fn round_trip_mesh_document() {
+        let document = MeshDocument::import("assets/cube/cube.obj").unwrap();
+        let scene = Scene::from_mesh_document(document).unwrap();
+        let reimported = reimport(&scene);
+        assert_same_geometry(&scene, &reimported);
+        assert!(reimported.materials[0].normal_texture.is_some());
+    }
+
+    #[test]
+    fn round_trip_edits() {
+        let mut scene =
+            import("assets/glTF-Sample-Models/2.0/DamagedHelmet/glTF-Binary/DamagedHelmet.glb");
+        let node = scene
+            .nodes
+            .iter()
+            .position(|node| node.mesh.is_some())
+            .unwrap();
+        scene.nodes[node].transform = Mat4::from_translation(glam::vec3(1., 2., 3.));
+        scene.nodes[node].instances = vec![
+            Mat4::IDENTITY,
+            Mat4::from_scale_rotation_translation(
+                glam::Vec3::splat(2.),
+                glam::Quat::from_rotation_y(1.),
+                glam::vec3(0., 5., 0.),
+            ),
+        ];
+        let reimported = reimport(&scene);
+        assert_same_geometry(&scene, &reimported);
+        assert!(reimported
+            .extensions_used
+            .contains(&"EXT_mesh_gpu_instancing".to_owned()));
+    }
+
+    #[test]
+    fn round_trip_active_variant() {
+        let mut scene =
+            import("assets/glTF-Sample-Models/2.0/DamagedHelmet/glTF-Binary/DamagedHelmet.glb");
+        let mut material = scene.materials[0].clone();
+        material.name = Some("Variant".to_owned());
+        scene.materials.push(material);
+        scene.variants = vec!["Default".to_owned(), "Variant".to_owned()];
+        scene.meshes[0].primitives[0].variant_materials = HashMap::from([(0, 0), (1, 1)]);
+
+        fn primitive(scene: &Scene) -> &ScenePrimitive {
+            &scene.meshes[0].primitives[0]
+        }
+        let reimported = reimport_variant(&scene, None);
+        assert_eq!(primitive(&reimported).material, Some(0));
+        assert_eq!(reimported.variants, scene.variants);
+
+        // The variant shown in the viewer becomes the default material
+        let reimported = reimport_variant(&scene, Some(1));
+        assert_eq!(primitive(&reimported).material, Some(1));
+        assert_eq!(
+            primitive(&reimported).variant_materials,
+            primitive(&scene).variant_materials
+        );
+        assert_eq!(reimported.variants, scene.variants);
+    }
+}
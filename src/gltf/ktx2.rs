@@ -0,0 +1,300 @@
+//! KTX2 container parsing for `KHR_texture_basisu`.
+
+use std::{borrow::Cow, io::Read};
+
+use basisu::{DecodeFlags, TargetFormat, Transcoder};
+use color_eyre::{
+    eyre::{bail, ensure, eyre, ContextCompat},
+    Result,
+};
+use wgpu::TextureFormat;
+
+const IDENTIFIER: [u8; 12] = [
+    0xab, 0x4b, 0x54, 0x58, 0x20, 0x32, 0x30, 0xbb, 0x0d, 0x0a, 0x1a, 0x0a,
+];
+const HEADER_SIZE: usize = 80;
+const LEVEL_INDEX_ENTRY_SIZE: usize = 24;
+
+const KHR_DF_MODEL_ETC1S: u8 = 163;
+const KHR_DF_MODEL_UASTC: u8 = 166;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Supercompression {
+    None,
+    BasisLz,
+    Zstandard,
+    Zlib,
+}
+
+/// Mip levels of a 2D KTX2 texture, largest first.
+#[derive(Debug)]
+pub struct Ktx2 {
+    pub vk_format: u32,
+    pub width: u32,
+    pub height: u32,
+    pub supercompression: Supercompression,
+    /// Color model from the data format descriptor
+    pub color_model: u8,
+    /// Empty for Basis Universal payloads, those are transcoded from `basis` instead
+    pub levels: Vec<Vec<u8>>,
+    /// Whole container of Basis Universal payloads
+    basis: Option<Vec<u8>>,
+}
+
+impl Ktx2 {
+    pub fn is_ktx2(data: &[u8]) -> bool {
+        data.starts_with(&IDENTIFIER)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self> {
+        ensure!(Self::is_ktx2(data), "KTX2: Invalid identifier.");
+        ensure!(data.len() >= HEADER_SIZE, "KTX2: Truncated header.");
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+
+        let vk_format = u32_at(12);
+        let width = u32_at(20);
+        let height = u32_at(24).max(1);
+        let depth = u32_at(28);
+        let layers = u32_at(32);
+        let faces = u32_at(36);
+        let level_count = u32_at(40).max(1) as usize;
+        ensure!(
+            depth <= 1 && layers <= 1 && faces == 1,
+            "KTX2: Only 2D textures are supported."
+        );
+        let supercompression = match u32_at(44) {
+            0 => Supercompression::None,
+            1 => Supercompression::BasisLz,
+            2 => Supercompression::Zstandard,
+            3 => Supercompression::Zlib,
+            scheme => bail!("KTX2: Unknown supercompression scheme {scheme}."),
+        };
+
+        let dfd_offset = u32_at(48) as usize;
+        // colorModel of the first basic descriptor block
+        let color_model = *data
+            .get(dfd_offset + 12)
+            .context("KTX2: Truncated data format descriptor.")?;
+        if is_basis(supercompression, color_model) {
+            return Ok(Self {
+                vk_format,
+                width,
+                height,
+                supercompression,
+                color_model,
+                levels: vec![],
+                basis: Some(data.to_vec()),
+            });
+        }
+
+        ensure!(
+            data.len() >= HEADER_SIZE + level_count * LEVEL_INDEX_ENTRY_SIZE,
+            "KTX2: Truncated level index."
+        );
+        let levels = (0..level_count)
+            .map(|level| {
+                let entry = HEADER_SIZE + level * LEVEL_INDEX_ENTRY_SIZE;
+                let offset = u64_at(entry) as usize;
+                let length = u64_at(entry + 8) as usize;
+                let level = data
+                    .get(offset..offset + length)
+                    .context("KTX2: Level data out of bounds.")?;
+                let mut decoder: Box<dyn Read> = match supercompression {
+                    Supercompression::Zlib => Box::new(flate2::read::ZlibDecoder::new(level)),
+                    Supercompression::Zstandard => Box::new(ruzstd::StreamingDecoder::new(level)?),
+                    _ => return Ok(level.to_vec()),
+                };
+                let mut inflated = Vec::with_capacity(u64_at(entry + 16) as usize);
+                decoder.read_to_end(&mut inflated)?;
+                Ok(inflated)
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            vk_format,
+            width,
+            height,
+            supercompression,
+            color_model,
+            levels,
+            basis: None,
+        })
+    }
+
+    /// Whether the payload is Basis Universal data that has to be transcoded first.
+    pub fn is_basis(&self) -> bool {
+        is_basis(self.supercompression, self.color_model)
+    }
+
+    /// Level data ready for upload in the given format. Basis Universal payloads are
+    /// transcoded to a block format the device `features` allow, or to RGBA8 without one.
+    pub fn texture_data(
+        &self,
+        features: wgpu::Features,
+    ) -> Result<(TextureFormat, Cow<'_, [Vec<u8>]>)> {
+        let Some(data) = &self.basis else {
+            let format = vk_format_to_texture_format(self.vk_format)
+                .with_context(|| format!("KTX2: Unsupported vkFormat {}.", self.vk_format))?;
+            return Ok((format, Cow::Borrowed(&self.levels)));
+        };
+
+        let transcoder = Transcoder::new(data)
+            .map_err(|err| eyre!("KTX2: Invalid Basis Universal data: {err:?}."))?;
+        let (target, format) = self.transcode_target(features);
+        let levels = (0..transcoder.level_count())
+            .map(|level| {
+                transcoder
+                    .transcode(level, target, DecodeFlags::NONE)
+                    .map_err(|err| eyre!("KTX2: Failed to transcode level {level}: {err:?}."))
+            })
+            .collect::<Result<_>>()?;
+        Ok((format, Cow::Owned(levels)))
+    }
+
+    /// Block formats need the texture size to be a multiple of 4.
+    fn transcode_target(&self, features: wgpu::Features) -> (TargetFormat, TextureFormat) {
+        use wgpu::{AstcBlock, AstcChannel, Features};
+        if self.width.is_multiple_of(4) && self.height.is_multiple_of(4) {
+            if features.contains(Features::TEXTURE_COMPRESSION_BC) {
+                return (TargetFormat::Bc7Rgba, TextureFormat::Bc7RgbaUnorm);
+            }
+            if features.contains(Features::TEXTURE_COMPRESSION_ASTC_LDR) {
+                let format = TextureFormat::Astc {
+                    block: AstcBlock::B4x4,
+                    channel: AstcChannel::Unorm,
+                };
+                return (TargetFormat::Astc4x4Rgba, format);
+            }
+            if features.contains(Features::TEXTURE_COMPRESSION_ETC2) {
+                return (TargetFormat::Etc2Rgba, TextureFormat::Etc2Rgba8Unorm);
+            }
+        }
+        (TargetFormat::Rgba32, TextureFormat::Rgba8Unorm)
+    }
+}
+
+fn is_basis(supercompression: Supercompression, color_model: u8) -> bool {
+    supercompression == Supercompression::BasisLz
+        || matches!(color_model, KHR_DF_MODEL_ETC1S | KHR_DF_MODEL_UASTC)
+}
+
+/// sRGB formats map to their linear variant, same as decoded images.
+fn vk_format_to_texture_format(vk_format: u32) -> Option<TextureFormat> {
+    use wgpu::{AstcBlock, AstcChannel};
+    use TextureFormat::*;
+    let format = match vk_format {
+        9 => R8Unorm,
+        16 => Rg8Unorm,
+        37 | 43 => Rgba8Unorm,
+        97 => Rgba16Float,
+        131..=134 => Bc1RgbaUnorm,
+        135 | 136 => Bc2RgbaUnorm,
+        137 | 138 => Bc3RgbaUnorm,
+        139 => Bc4RUnorm,
+        140 => Bc4RSnorm,
+        141 => Bc5RgUnorm,
+        142 => Bc5RgSnorm,
+        143 => Bc6hRgbUfloat,
+        144 => Bc6hRgbSfloat,
+        145 | 146 => Bc7RgbaUnorm,
+        147 | 148 => Etc2Rgb8Unorm,
+        149 | 150 => Etc2Rgb8A1Unorm,
+        151 | 152 => Etc2Rgba8Unorm,
+        153 => EacR11Unorm,
+        154 => EacR11Snorm,
+        155 => EacRg11Unorm,
+        156 => EacRg11Snorm,
+        157 | 158 => Astc {
+            block: AstcBlock::B4x4,
+            channel: AstcChannel::Unorm,
+        },
+        _ => return None,
+    };
+    Some(format)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::iter::zip;
+
+    use wgpu::Features;
+
+    use super::*;
+
+    /// 16x16 source of the fixtures, the right half is half transparent.
+    fn gradient(x: usize, y: usize) -> [u8; 4] {
+        let checker = if (x / 4 + y / 4) % 2 == 1 { 255 } else { 32 };
+        let alpha = if x < 8 { 255 } else { 128 };
+        [x * 16 + 8, y * 16 + 8, checker, alpha].map(|c| c as u8)
+    }
+
+    fn check_transcoding(file: &[u8], supercompression: Supercompression, tolerance: u8) {
+        let ktx2 = Ktx2::parse(file).unwrap();
+        assert!(ktx2.is_basis());
+        assert_eq!(ktx2.supercompression, supercompression);
+        assert_eq!((ktx2.width, ktx2.height), (16, 16));
+
+        let (format, levels) = ktx2.texture_data(Features::empty()).unwrap();
+        assert_eq!(format, TextureFormat::Rgba8Unorm);
+        let sizes: Vec<_> = levels.iter().map(Vec::len).collect();
+        assert_eq!(
+            sizes,
+            [16 * 16, 8 * 8, 4 * 4, 2 * 2, 1].map(|texels| texels * 4)
+        );
+        let max_error = levels[0]
+            .chunks_exact(4)
+            .enumerate()
+            .flat_map(|(index, texel)| {
+                let expected = gradient(index % 16, index / 16);
+                zip(texel, expected).map(|(value, expected)| value.abs_diff(expected))
+            })
+            .max();
+        assert!(max_error <= Some(tolerance), "max error {max_error:?}");
+
+        let block_formats = [
+            (
+                Features::TEXTURE_COMPRESSION_BC,
+                TextureFormat::Bc7RgbaUnorm,
+            ),
+            (
+                Features::TEXTURE_COMPRESSION_ETC2,
+                TextureFormat::Etc2Rgba8Unorm,
+            ),
+            (
+                Features::TEXTURE_COMPRESSION_ASTC_LDR,
+                TextureFormat::Astc {
+                    block: wgpu::AstcBlock::B4x4,
+                    channel: wgpu::AstcChannel::Unorm,
+                },
+            ),
+        ];
+        for (features, expected) in block_formats {
+            let (format, levels) = ktx2.texture_data(features).unwrap();
+            assert_eq!(format, expected);
+            // 16 bytes per block, smaller levels still take a whole one
+            let sizes: Vec<_> = levels.iter().map(Vec::len).collect();
+            assert_eq!(sizes, [16 * 16, 4 * 16, 16, 16, 16]);
+        }
+    }
+
+    #[test]
+    fn transcode_etc1s() {
+        let file = include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/assets/ktx2/gradient_etc1s.ktx2"
+        ));
+        check_transcoding(file, Supercompression::BasisLz, 32);
+    }
+
+    #[test]
+    fn transcode_uastc_zstd() {
+        let file = include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/assets/ktx2/gradient_uastc_zstd.ktx2"
+        ));
+        check_transcoding(file, Supercompression::Zstandard, 4);
+    }
+}
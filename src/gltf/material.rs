@@ -0,0 +1,96 @@
+use gltf::json::Value;
+
+/// `KHR_materials_specular`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Specular {
+    pub factor: f32,
+    pub texture: Option<usize>,
+    pub color_factor: [f32; 3],
+    pub color_texture: Option<usize>,
+}
+
+/// `KHR_materials_iridescence`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Iridescence {
+    pub factor: f32,
+    pub texture: Option<usize>,
+    pub ior: f32,
+    pub thickness_minimum: f32,
+    pub thickness_maximum: f32,
+    pub thickness_texture: Option<usize>,
+}
+
+/// `KHR_materials_anisotropy`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Anisotropy {
+    pub strength: f32,
+    pub rotation: f32,
+    pub texture: Option<usize>,
+}
+
+/// Material extensions layered on top of the metallic-roughness model.
+/// Textures are stored as indices into the document's textures.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct MaterialExtensions {
+    pub specular: Option<Specular>,
+    pub iridescence: Option<Iridescence>,
+    pub anisotropy: Option<Anisotropy>,
+}
+
+impl MaterialExtensions {
+    pub fn from_material(material: &gltf::Material) -> Self {
+        let specular = material.specular().map(|specular| Specular {
+            factor: specular.specular_factor(),
+            texture: specular.specular_texture().map(|t| t.texture().index()),
+            color_factor: specular.specular_color_factor(),
+            color_texture: specular
+                .specular_color_texture()
+                .map(|t| t.texture().index()),
+        });
+
+        // Not covered by the `gltf` crate yet, so read them from raw json
+        let iridescence = material
+            .extension_value("KHR_materials_iridescence")
+            .map(|ext| Iridescence {
+                factor: json_f32(ext, "iridescenceFactor").unwrap_or(0.0),
+                texture: json_texture(ext, "iridescenceTexture"),
+                ior: json_f32(ext, "iridescenceIor").unwrap_or(1.3),
+                thickness_minimum: json_f32(ext, "iridescenceThicknessMinimum").unwrap_or(100.0),
+                thickness_maximum: json_f32(ext, "iridescenceThicknessMaximum").unwrap_or(400.0),
+                thickness_texture: json_texture(ext, "iridescenceThicknessTexture"),
+            });
+        let anisotropy = material
+            .extension_value("KHR_materials_anisotropy")
+            .map(|ext| Anisotropy {
+                strength: json_f32(ext, "anisotropyStrength").unwrap_or(0.0),
+                rotation: json_f32(ext, "anisotropyRotation").unwrap_or(0.0),
+                texture: json_texture(ext, "anisotropyTexture"),
+            });
+
+        Self {
+            specular,
+            iridescence,
+            anisotropy,
+        }
+    }
+
+    /// Indices of every texture referenced by the extensions.
+    pub fn textures(&self) -> impl Iterator<Item = usize> {
+        let specular = self.specular.map(|s| [s.texture, s.color_texture]);
+        let iridescence = self.iridescence.map(|i| [i.texture, i.thickness_texture]);
+        let anisotropy = self.anisotropy.map(|a| [a.texture, None]);
+        [specular, iridescence, anisotropy]
+            .into_iter()
+            .flatten()
+            .flatten()
+            .flatten()
+    }
+}
+
+fn json_f32(value: &Value, key: &str) -> Option<f32> {
+    value.get(key)?.as_f64().map(|v| v as f32)
+}
+
+fn json_texture(value: &Value, key: &str) -> Option<usize> {
+    value.get(key)?.get("index")?.as_u64().map(|v| v as usize)
+}
@@ -0,0 +1,720 @@
+//! Decoder for `EXT_meshopt_compression` buffer views.
+
+use color_eyre::{
+    eyre::{bail, ensure, ContextCompat},
+    Result,
+};
+
+const VERTEX_HEADER: u8 = 0xa0;
+const INDEX_HEADER: u8 = 0xe0;
+const SEQUENCE_HEADER: u8 = 0xd0;
+
+const BYTE_GROUP_SIZE: usize = 16;
+const VERTEX_BLOCK_SIZE_BYTES: usize = 8192;
+const VERTEX_BLOCK_MAX_SIZE: usize = 256;
+const TAIL_MAX_SIZE: usize = 32;
+
+/// Compressed buffer view as stored in the extension object.
+#[derive(Debug)]
+pub struct CompressedView {
+    pub buffer: usize,
+    pub byte_offset: usize,
+    pub byte_length: usize,
+    pub byte_stride: usize,
+    pub count: usize,
+    pub mode: Mode,
+    pub filter: Filter,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Attributes,
+    Triangles,
+    Indices,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Filter {
+    None,
+    Octahedral,
+    Quaternion,
+    Exponential,
+}
+
+impl CompressedView {
+    pub fn from_view(view: &gltf::buffer::View) -> Result<Option<Self>> {
+        let Some(ext) = view.extension_value("EXT_meshopt_compression") else {
+            return Ok(None);
+        };
+        let usize_field = |key: &str| ext.get(key).and_then(|v| v.as_u64()).map(|v| v as usize);
+        let mode = match ext.get("mode").and_then(|v| v.as_str()) {
+            Some("ATTRIBUTES") => Mode::Attributes,
+            Some("TRIANGLES") => Mode::Triangles,
+            Some("INDICES") => Mode::Indices,
+            mode => bail!("Invalid GLTF: Unknown meshopt mode {mode:?}."),
+        };
+        let filter = match ext.get("filter").and_then(|v| v.as_str()) {
+            None | Some("NONE") => Filter::None,
+            Some("OCTAHEDRAL") => Filter::Octahedral,
+            Some("QUATERNION") => Filter::Quaternion,
+            Some("EXPONENTIAL") => Filter::Exponential,
+            Some(filter) => bail!("Invalid GLTF: Unknown meshopt filter {filter:?}."),
+        };
+        Ok(Some(Self {
+            buffer: usize_field("buffer").context("Invalid GLTF: Meshopt view without buffer.")?,
+            byte_offset: usize_field("byteOffset").unwrap_or(0),
+            byte_length: usize_field("byteLength")
+                .context("Invalid GLTF: Meshopt view without byteLength.")?,
+            byte_stride: usize_field("byteStride")
+                .context("Invalid GLTF: Meshopt view without byteStride.")?,
+            count: usize_field("count").context("Invalid GLTF: Meshopt view without count.")?,
+            mode,
+            filter,
+        }))
+    }
+
+    /// Decodes the compressed data into `count * byte_stride` bytes.
+    pub fn decode(&self, buffers: &[gltf::buffer::Data]) -> Result<Vec<u8>> {
+        let source = buffers
+            .get(self.buffer)
+            .and_then(|buffer| buffer.get(self.byte_offset..self.byte_offset + self.byte_length))
+            .context("Invalid GLTF: Meshopt data out of buffer bounds.")?;
+        let mut data = vec![0; self.count * self.byte_stride];
+        match self.mode {
+            Mode::Attributes => decode_vertex_buffer(&mut data, self.byte_stride, source)?,
+            Mode::Triangles => decode_index_buffer(&mut data, self.byte_stride, source)?,
+            Mode::Indices => decode_index_sequence(&mut data, self.byte_stride, source)?,
+        }
+        match self.filter {
+            Filter::None => {}
+            Filter::Octahedral => decode_octahedral_filter(&mut data, self.byte_stride)?,
+            Filter::Quaternion => decode_quaternion_filter(&mut data, self.byte_stride)?,
+            Filter::Exponential => decode_exponential_filter(&mut data, self.byte_stride)?,
+        }
+        Ok(data)
+    }
+}
+
+/// Whether the buffer only exists as a placeholder for decompressed data.
+pub fn is_fallback_buffer(buffer: &gltf::Buffer) -> bool {
+    buffer
+        .extension_value("EXT_meshopt_compression")
+        .and_then(|ext| ext.get("fallback"))
+        .and_then(|fallback| fallback.as_bool())
+        .unwrap_or(false)
+}
+
+/// Decompresses every compressed buffer view into its place in the target buffer.
+pub fn decompress_buffer_views(
+    document: &gltf::Document,
+    buffers: &mut [gltf::buffer::Data],
+) -> Result<()> {
+    for view in document.views() {
+        let Some(compressed) = CompressedView::from_view(&view)? else {
+            continue;
+        };
+        let data = compressed.decode(buffers)?;
+        let target = buffers[view.buffer().index()]
+            .0
+            .get_mut(view.offset()..view.offset() + data.len())
+            .context("Invalid GLTF: Meshopt view out of buffer bounds.")?;
+        target.copy_from_slice(&data);
+    }
+    Ok(())
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn byte(&mut self) -> Result<u8> {
+        let byte = *self
+            .data
+            .get(self.position)
+            .context("Meshopt: Unexpected end of data.")?;
+        self.position += 1;
+        Ok(byte)
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8]> {
+        let bytes = self
+            .data
+            .get(self.position..self.position + len)
+            .context("Meshopt: Unexpected end of data.")?;
+        self.position += len;
+        Ok(bytes)
+    }
+
+    fn vbyte(&mut self) -> Result<u32> {
+        let lead = self.byte()?;
+        if lead < 128 {
+            return Ok(lead as u32);
+        }
+        let mut result = (lead & 127) as u32;
+        let mut shift = 7;
+        for _ in 0..4 {
+            let group = self.byte()?;
+            result |= ((group & 127) as u32) << shift;
+            shift += 7;
+            if group < 128 {
+                break;
+            }
+        }
+        Ok(result)
+    }
+}
+
+fn unzigzag8(v: u8) -> u8 {
+    (v & 1).wrapping_neg() ^ (v >> 1)
+}
+
+fn unzigzag32(v: u32) -> u32 {
+    (v & 1).wrapping_neg() ^ (v >> 1)
+}
+
+fn vertex_block_size(vertex_size: usize) -> usize {
+    let result = (VERTEX_BLOCK_SIZE_BYTES / vertex_size) & !(BYTE_GROUP_SIZE - 1);
+    result.min(VERTEX_BLOCK_MAX_SIZE)
+}
+
+fn decode_bytes_group(reader: &mut Reader, buffer: &mut [u8], bitslog2: u8) -> Result<()> {
+    match bitslog2 {
+        0 => buffer.fill(0),
+        3 => buffer.copy_from_slice(reader.bytes(BYTE_GROUP_SIZE)?),
+        _ => {
+            let bits = 1 << bitslog2;
+            let packed = reader.bytes(BYTE_GROUP_SIZE * bits / 8)?;
+            let escape = (1u8 << bits) - 1;
+            for (i, value) in buffer.iter_mut().enumerate() {
+                let byte = packed[i * bits / 8];
+                let shift = 8 - bits - (i * bits) % 8;
+                let encoded = (byte >> shift) & escape;
+                *value = if encoded == escape {
+                    reader.byte()?
+                } else {
+                    encoded
+                };
+            }
+        }
+    }
+    Ok(())
+}
+
+fn decode_bytes(reader: &mut Reader, buffer: &mut [u8]) -> Result<()> {
+    let groups = buffer.len() / BYTE_GROUP_SIZE;
+    let header = reader.bytes(groups.div_ceil(4))?;
+    for (i, group) in buffer.chunks_exact_mut(BYTE_GROUP_SIZE).enumerate() {
+        let bitslog2 = (header[i / 4] >> ((i % 4) * 2)) & 3;
+        decode_bytes_group(reader, group, bitslog2)?;
+    }
+    Ok(())
+}
+
+pub fn decode_vertex_buffer(destination: &mut [u8], vertex_size: usize, data: &[u8]) -> Result<()> {
+    ensure!(
+        vertex_size > 0 && vertex_size <= 256 && vertex_size.is_multiple_of(4),
+        "Meshopt: Invalid vertex size {vertex_size}."
+    );
+    let tail_size = vertex_size.max(TAIL_MAX_SIZE);
+    ensure!(data.len() > tail_size, "Meshopt: Vertex data too short.");
+    ensure!(
+        data[0] == VERTEX_HEADER,
+        "Meshopt: Unsupported vertex codec header {:#x}.",
+        data[0]
+    );
+
+    let (body, tail) = data.split_at(data.len() - tail_size);
+    let mut last_vertex = tail[tail_size - vertex_size..].to_vec();
+    let mut reader = Reader {
+        data: body,
+        position: 1,
+    };
+
+    let block_size = vertex_block_size(vertex_size);
+    let mut buffer = [0; VERTEX_BLOCK_MAX_SIZE];
+    for block in destination.chunks_mut(block_size * vertex_size) {
+        let vertex_count = block.len() / vertex_size;
+        let aligned_count = vertex_count.next_multiple_of(BYTE_GROUP_SIZE);
+        for k in 0..vertex_size {
+            decode_bytes(&mut reader, &mut buffer[..aligned_count])?;
+            let mut previous = last_vertex[k];
+            for (i, &delta) in buffer[..vertex_count].iter().enumerate() {
+                previous = unzigzag8(delta).wrapping_add(previous);
+                block[i * vertex_size + k] = previous;
+            }
+        }
+        last_vertex.copy_from_slice(&block[block.len() - vertex_size..]);
+    }
+    ensure!(
+        reader.position == body.len(),
+        "Meshopt: Vertex data has trailing bytes."
+    );
+    Ok(())
+}
+
+/// Ring buffers of recently seen edges and vertices shared with the encoder.
+#[derive(Default)]
+struct EdgeFifo {
+    entries: [(u32, u32); 16],
+    offset: usize,
+}
+
+impl EdgeFifo {
+    fn get(&self, fe: usize) -> (u32, u32) {
+        self.entries[self.offset.wrapping_sub(1 + fe) & 15]
+    }
+
+    fn push(&mut self, a: u32, b: u32) {
+        self.entries[self.offset] = (a, b);
+        self.offset = (self.offset + 1) & 15;
+    }
+}
+
+#[derive(Default)]
+struct VertexFifo {
+    entries: [u32; 16],
+    offset: usize,
+}
+
+impl VertexFifo {
+    fn get(&self, fe: usize) -> u32 {
+        self.entries[self.offset.wrapping_sub(fe) & 15]
+    }
+
+    fn push(&mut self, v: u32, advance: bool) {
+        self.entries[self.offset] = v;
+        self.offset = (self.offset + advance as usize) & 15;
+    }
+}
+
+fn post_increment(value: &mut u32) -> u32 {
+    *value += 1;
+    *value - 1
+}
+
+fn write_index(destination: &mut [u8], index_size: usize, i: usize, value: u32) {
+    let bytes = value.to_le_bytes();
+    destination[i * index_size..(i + 1) * index_size].copy_from_slice(&bytes[..index_size]);
+}
+
+pub fn decode_index_buffer(destination: &mut [u8], index_size: usize, data: &[u8]) -> Result<()> {
+    ensure!(
+        index_size == 2 || index_size == 4,
+        "Meshopt: Invalid index size {index_size}."
+    );
+    let index_count = destination.len() / index_size;
+    ensure!(
+        index_count.is_multiple_of(3),
+        "Meshopt: Index count is not a multiple of 3."
+    );
+    let triangle_count = index_count / 3;
+    ensure!(
+        data.len() >= 1 + triangle_count + 16,
+        "Meshopt: Index data too short."
+    );
+    ensure!(
+        data[0] & 0xf0 == INDEX_HEADER && data[0] & 0x0f <= 1,
+        "Meshopt: Unsupported index codec header {:#x}.",
+        data[0]
+    );
+    let version = data[0] & 0x0f;
+
+    let codes = &data[1..1 + triangle_count];
+    let (body, codeaux_table) = data.split_at(data.len() - 16);
+    let mut reader = Reader {
+        data: body,
+        position: 1 + triangle_count,
+    };
+
+    let mut edges = EdgeFifo::default();
+    let mut vertices = VertexFifo::default();
+    let mut next = 0u32;
+    let mut last = 0u32;
+    let fec_max = if version >= 1 { 13 } else { 15 };
+
+    for (triangle, &code) in codes.iter().enumerate() {
+        let (a, b, c) = if code < 0xf0 {
+            let (a, b) = edges.get((code >> 4) as usize);
+            let fec = (code & 15) as u32;
+            let c = if fec < fec_max {
+                let c = match fec {
+                    0 => post_increment(&mut next),
+                    fec => vertices.get(fec as usize + 1),
+                };
+                vertices.push(c, fec == 0);
+                c
+            } else {
+                last = if fec != 15 {
+                    // 13 and 14 encode -1 and 1
+                    last.wrapping_add(fec.wrapping_sub(fec ^ 3))
+                } else {
+                    last.wrapping_add(unzigzag32(reader.vbyte()?))
+                };
+                vertices.push(last, true);
+                last
+            };
+            (a, b, c)
+        } else {
+            let (codeaux, fea) = if code < 0xfe {
+                (codeaux_table[(code & 15) as usize], 0)
+            } else {
+                let codeaux = reader.byte()?;
+                if codeaux == 0 {
+                    next = 0;
+                }
+                (codeaux, if code == 0xfe { 0 } else { 15 })
+            };
+            let feb = (codeaux >> 4) as usize;
+            let fec = (codeaux & 15) as usize;
+
+            // all three vertices advance `next` before free indices are decoded
+            let mut a = if fea == 0 {
+                post_increment(&mut next)
+            } else {
+                0
+            };
+            let mut b = match feb {
+                0 => post_increment(&mut next),
+                feb => vertices.get(feb),
+            };
+            let mut c = match fec {
+                0 => post_increment(&mut next),
+                fec => vertices.get(fec),
+            };
+            for (fe, index) in [(fea, &mut a), (feb, &mut b), (fec, &mut c)] {
+                if fe == 15 {
+                    last = last.wrapping_add(unzigzag32(reader.vbyte()?));
+                    *index = last;
+                }
+            }
+            vertices.push(a, true);
+            vertices.push(b, feb == 0 || feb == 15);
+            vertices.push(c, fec == 0 || fec == 15);
+            edges.push(b, a);
+            (a, b, c)
+        };
+        edges.push(c, b);
+        edges.push(a, c);
+
+        write_index(destination, index_size, triangle * 3, a);
+        write_index(destination, index_size, triangle * 3 + 1, b);
+        write_index(destination, index_size, triangle * 3 + 2, c);
+    }
+    ensure!(
+        reader.position == body.len(),
+        "Meshopt: Index data has trailing bytes."
+    );
+    Ok(())
+}
+
+pub fn decode_index_sequence(destination: &mut [u8], index_size: usize, data: &[u8]) -> Result<()> {
+    ensure!(
+        index_size == 2 || index_size == 4,
+        "Meshopt: Invalid index size {index_size}."
+    );
+    let index_count = destination.len() / index_size;
+    ensure!(
+        data.len() >= 1 + index_count + 4,
+        "Meshopt: Index sequence data too short."
+    );
+    ensure!(
+        data[0] & 0xf0 == SEQUENCE_HEADER && data[0] & 0x0f <= 1,
+        "Meshopt: Unsupported index sequence header {:#x}.",
+        data[0]
+    );
+
+    let body = &data[..data.len() - 4];
+    let mut reader = Reader {
+        data: body,
+        position: 1,
+    };
+    let mut last = [0u32; 2];
+    for i in 0..index_count {
+        let v = reader.vbyte()?;
+        let baseline = (v & 1) as usize;
+        let index = last[baseline].wrapping_add(unzigzag32(v >> 1));
+        last[baseline] = index;
+        write_index(destination, index_size, i, index);
+    }
+    ensure!(
+        reader.position == body.len(),
+        "Meshopt: Index sequence data has trailing bytes."
+    );
+    Ok(())
+}
+
+fn round_to_int(value: f32) -> i32 {
+    (value + if value >= 0. { 0.5 } else { -0.5 }) as i32
+}
+
+pub fn decode_octahedral_filter(data: &mut [u8], stride: usize) -> Result<()> {
+    fn decode(one: f32, x: f32, y: f32, max: f32) -> [i32; 3] {
+        let (mut x, mut y) = (x / one, y / one);
+        let z = 1. - x.abs() - y.abs();
+        // fixup for the negative hemisphere
+        let t = (-z).max(0.);
+        x -= if x >= 0. { t } else { -t };
+        y -= if y >= 0. { t } else { -t };
+        let scale = max / (x * x + y * y + z * z).sqrt();
+        [x, y, z].map(|v| round_to_int(v * scale))
+    }
+    match stride {
+        4 => {
+            for v in data.chunks_exact_mut(4) {
+                let [x, y, one, _] = [v[0], v[1], v[2], v[3]].map(|c| c as i8 as f32);
+                let decoded = decode(one, x, y, 127.);
+                for (c, value) in decoded.into_iter().enumerate() {
+                    v[c] = value as i8 as u8;
+                }
+            }
+        }
+        8 => {
+            for v in data.chunks_exact_mut(8) {
+                let component = |c: usize| i16::from_le_bytes([v[c * 2], v[c * 2 + 1]]) as f32;
+                let decoded = decode(component(2), component(0), component(1), 32767.);
+                for (c, value) in decoded.into_iter().enumerate() {
+                    v[c * 2..c * 2 + 2].copy_from_slice(&(value as i16).to_le_bytes());
+                }
+            }
+        }
+        _ => bail!("Meshopt: Invalid octahedral filter stride {stride}."),
+    }
+    Ok(())
+}
+
+pub fn decode_quaternion_filter(data: &mut [u8], stride: usize) -> Result<()> {
+    ensure!(
+        stride == 8,
+        "Meshopt: Invalid quaternion filter stride {stride}."
+    );
+    let scale = std::f32::consts::FRAC_1_SQRT_2;
+    for v in data.chunks_exact_mut(8) {
+        let q: [i16; 4] = std::array::from_fn(|c| i16::from_le_bytes([v[c * 2], v[c * 2 + 1]]));
+        // scale is stored in the high bits of the last component
+        let ss = scale / (q[3] | 3) as f32;
+        let [x, y, z] = [q[0], q[1], q[2]].map(|c| c as f32 * ss);
+        let w = (1. - x * x - y * y - z * z).max(0.).sqrt();
+
+        let max_component = (q[3] & 3) as usize;
+        for (offset, value) in [(1, x), (2, y), (3, z), (0, w)] {
+            let c = (max_component + offset) & 3;
+            let value = round_to_int(value * 32767.) as i16;
+            v[c * 2..c * 2 + 2].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+    Ok(())
+}
+
+pub fn decode_exponential_filter(data: &mut [u8], stride: usize) -> Result<()> {
+    ensure!(
+        stride.is_multiple_of(4),
+        "Meshopt: Invalid exponential filter stride {stride}."
+    );
+    for v in data.chunks_exact_mut(4) {
+        let value = i32::from_le_bytes([v[0], v[1], v[2], v[3]]);
+        let mantissa = (value << 8) >> 8;
+        let exponent = value >> 24;
+        let scale = f32::from_bits(((exponent + 127) as u32) << 23);
+        v.copy_from_slice(&(mantissa as f32 * scale).to_le_bytes());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    //! Encoded vectors from the meshoptimizer test suite.
+
+    use super::*;
+    use crate::gltf::GltfDocument;
+
+    fn le_bytes<const N: usize>(values: &[[u16; N]]) -> Vec<u8> {
+        values
+            .iter()
+            .flatten()
+            .flat_map(|v| v.to_le_bytes())
+            .collect()
+    }
+
+    fn decode_indices(
+        decode: fn(&mut [u8], usize, &[u8]) -> Result<()>,
+        data: &[u8],
+        count: usize,
+    ) -> (Vec<u16>, Vec<u32>) {
+        let mut short = vec![0; count * 2];
+        decode(&mut short, 2, data).unwrap();
+        let mut long = vec![0; count * 4];
+        decode(&mut long, 4, data).unwrap();
+        (
+            bytemuck::pod_collect_to_vec(&short),
+            bytemuck::pod_collect_to_vec(&long),
+        )
+    }
+
+    const INDEX_DATA_V0: [u8; 27] = [
+        0xe0, 0xf0, 0x10, 0xfe, 0xff, 0xf0, 0x0c, 0xff, 0x02, 0x02, 0x02, 0x00, 0x76, 0x87, 0x56,
+        0x67, 0x78, 0xa9, 0x86, 0x65, 0x89, 0x68, 0x98, 0x01, 0x69, 0x00, 0x00,
+    ];
+    const INDEX_DATA_V1: [u8; 24] = [
+        0xe1, 0xf0, 0x10, 0xfe, 0x1f, 0x3d, 0x00, 0x0a, 0x00, 0x76, 0x87, 0x56, 0x67, 0x78, 0xa9,
+        0x86, 0x65, 0x89, 0x68, 0x98, 0x01, 0x69, 0x00, 0x00,
+    ];
+    const INDEX_SEQUENCE_V1: [u8; 13] = [
+        0xd1, 0x00, 0x04, 0xcd, 0x01, 0x04, 0x07, 0x98, 0x1f, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    #[test]
+    fn vertex_codec() {
+        let data = [
+            0xa0, 0x01, 0x3f, 0x00, 0x00, 0x00, 0x58, 0x57, 0x58, 0x01, 0x26, 0x00, 0x00, 0x00,
+            0x01, 0x0c, 0x00, 0x00, 0x00, 0x58, 0x01, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x01, 0x3f, 0x00, 0x00, 0x00, 0x17, 0x18, 0x17, 0x01, 0x26, 0x00, 0x00, 0x00,
+            0x01, 0x0c, 0x00, 0x00, 0x00, 0x17, 0x01, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00,
+        ];
+        // Position, octahedral normal and texture coordinate of a quad in 12 bytes
+        let expected = le_bytes(&[
+            [0, 0, 0, 0, 0, 0],
+            [300, 0, 0, 0, 500, 0],
+            [0, 300, 0, 0, 0, 500],
+            [300, 300, 0, 0, 500, 500],
+        ]);
+
+        let mut decoded = vec![0; expected.len()];
+        decode_vertex_buffer(&mut decoded, 12, &data).unwrap();
+        assert_eq!(decoded, expected);
+
+        for len in 0..data.len() {
+            assert!(decode_vertex_buffer(&mut decoded, 12, &data[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn index_codec() {
+        let expected = [0, 1, 2, 2, 1, 3, 4, 6, 5, 7, 8, 9];
+        let (short, long) = decode_indices(decode_index_buffer, &INDEX_DATA_V0, expected.len());
+        assert_eq!(short, expected.map(|i| i as u16));
+        assert_eq!(long, expected);
+
+        // Restarts and the last triangle's vertices only exist in version 1
+        let expected = [0, 1, 2, 2, 1, 3, 0, 1, 2, 2, 1, 5, 2, 1, 4];
+        let (short, long) = decode_indices(decode_index_buffer, &INDEX_DATA_V1, expected.len());
+        assert_eq!(short, expected.map(|i| i as u16));
+        assert_eq!(long, expected);
+
+        let mut decoded = vec![0; expected.len() * 4];
+        let mut invalid_version = INDEX_DATA_V1;
+        invalid_version[0] |= 0x0f;
+        assert!(decode_index_buffer(&mut decoded, 4, &invalid_version).is_err());
+        for len in 0..INDEX_DATA_V1.len() {
+            assert!(decode_index_buffer(&mut decoded, 4, &INDEX_DATA_V1[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn index_sequence_codec() {
+        let expected = [0, 1, 51, 2, 49, 1000];
+        let (short, long) = decode_indices(decode_index_sequence, &INDEX_SEQUENCE_V1, 6);
+        assert_eq!(short, expected.map(|i| i as u16));
+        assert_eq!(long, expected);
+
+        let mut decoded = vec![0; expected.len() * 4];
+        for len in 0..INDEX_SEQUENCE_V1.len() {
+            assert!(decode_index_sequence(&mut decoded, 4, &INDEX_SEQUENCE_V1[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn octahedral_filter() {
+        let mut data = [
+            0, 1, 127, 0, 0, 187, 127, 1, 255, 1, 127, 0, 14, 130, 127, 1,
+        ];
+        decode_octahedral_filter(&mut data, 4).unwrap();
+        assert_eq!(
+            data,
+            [0, 1, 127, 0, 0, 159, 82, 1, 255, 1, 127, 0, 1, 130, 241, 1]
+        );
+
+        let mut data = le_bytes(&[
+            [0, 1, 2047, 0],
+            [0, 1870, 2047, 1],
+            [2017, 1, 2047, 0],
+            [14, 1300, 2047, 1],
+        ]);
+        decode_octahedral_filter(&mut data, 8).unwrap();
+        let expected = le_bytes(&[
+            [0, 16, 32767, 0],
+            [0, 32621, 3088, 1],
+            [32764, 16, 471, 0],
+            [307, 28541, 16093, 1],
+        ]);
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn quaternion_filter() {
+        let mut data = le_bytes(&[
+            [0, 1, 0, 0x7fc],
+            [0, 1870, 0, 0x7fd],
+            [2017, 1, 0, 0x7fe],
+            [14, 1300, 0, 0x7ff],
+        ]);
+        decode_quaternion_filter(&mut data, 8).unwrap();
+        let expected = le_bytes(&[
+            [32767, 0, 11, 0],
+            [0, 25013, 0, 21166],
+            [11, 0, 23504, 22830],
+            [158, 14715, 0, 29277],
+        ]);
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn exponential_filter() {
+        let mut data: Vec<u8> = [0u32, 0xff000003, 0x02fffff7, 0xfe7fffff]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        decode_exponential_filter(&mut data, 4).unwrap();
+        let decoded: Vec<u32> = bytemuck::pod_collect_to_vec(&data);
+        assert_eq!(decoded, [0, 0x3fc00000, 0xc2100000, 0x49fffffe]);
+    }
+
+    #[test]
+    fn fallback_buffer_without_uri() {
+        let json = r#"{
+            "asset": { "version": "2.0" },
+            "extensionsUsed": ["EXT_meshopt_compression"],
+            "extensionsRequired": ["EXT_meshopt_compression"],
+            "buffers": [
+                { "uri": "compressed.bin", "byteLength": 13 },
+                { "byteLength": 24, "extensions": { "EXT_meshopt_compression": { "fallback": true } } }
+            ],
+            "bufferViews": [{
+                "buffer": 1,
+                "byteLength": 24,
+                "extensions": { "EXT_meshopt_compression": {
+                    "buffer": 0,
+                    "byteLength": 13,
+                    "byteStride": 4,
+                    "count": 6,
+                    "mode": "INDICES"
+                } }
+            }],
+            "accessors": [{ "bufferView": 0, "componentType": 5125, "count": 6, "type": "SCALAR" }]
+        }"#;
+        let resolver = |uri: &str| {
+            assert_eq!(uri, "compressed.bin");
+            Ok(INDEX_SEQUENCE_V1.to_vec())
+        };
+        let gltf = GltfDocument::from_slice(json.as_bytes(), &resolver).unwrap();
+
+        let accessor = gltf.document.accessors().next().unwrap();
+        let indices: Vec<u32> =
+            bytemuck::pod_collect_to_vec(&gltf.data_of_accessor(&accessor).unwrap());
+        assert_eq!(indices, [0, 1, 51, 2, 49, 1000]);
+    }
+}
@@ -1,34 +1,387 @@
-use std::path::Path;
+use std::{
+    borrow::Cow,
+    fmt::Display,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+};
 
-use color_eyre::Result;
+use color_eyre::{
+    eyre::{bail, ContextCompat},
+    Result,
+};
+use glam::{Mat4, Quat, Vec3};
+use image::{imageops::FilterType, RgbaImage};
 
+use crate::utils::par_map;
+
+mod accessor;
 mod conversions;
+pub mod export;
+mod ktx2;
+mod material;
+pub mod meshopt;
+mod resolver;
+mod spec_gloss;
 pub use conversions::*;
+pub use ktx2::Ktx2;
+pub use material::*;
+pub use resolver::{FileResolver, NoResolver, ResourceResolver};
+pub use spec_gloss::*;
+
+/// Extensions handled by wgltf itself on top of the ones the `gltf` crate validates.
+pub const SUPPORTED_EXTENSIONS: &[&str] = &[
+    "EXT_mesh_gpu_instancing",
+    "EXT_meshopt_compression",
+    "EXT_texture_webp",
+    "KHR_mesh_quantization",
+    "KHR_materials_anisotropy",
+    "KHR_materials_iridescence",
+    "KHR_materials_specular",
+    "KHR_materials_variants",
+    "KHR_texture_basisu",
+];
+
+/// Extensions that provide an alternative image source for a texture, most preferred first.
+const TEXTURE_SOURCE_EXTENSIONS: &[&str] = &["KHR_texture_basisu", "EXT_texture_webp"];
+
+pub enum Image {
+    /// Full mip chain, largest first
+    Rgba(Vec<RgbaImage>),
+    Ktx2(Ktx2),
+    /// Failed to decode, textures may still use a fallback source
+    Unsupported(String),
+}
+
+/// Counters of a model being loaded, safe to read from another thread.
+#[derive(Debug, Default)]
+pub struct LoadProgress {
+    /// Bytes of the document and its external resources read so far
+    pub bytes: AtomicUsize,
+    /// Images decoded with their mips generated
+    pub textures_done: AtomicUsize,
+    pub textures_total: AtomicUsize,
+    /// Primitives with vertices converted for upload
+    pub meshes_done: AtomicUsize,
+    pub meshes_total: AtomicUsize,
+}
+
+impl LoadProgress {
+    pub(crate) fn add(counter: &AtomicUsize, value: usize) {
+        counter.fetch_add(value, Ordering::Relaxed);
+    }
+}
+
+impl Display for LoadProgress {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let get = |counter: &AtomicUsize| counter.load(Ordering::Relaxed);
+        write!(
+            f,
+            "{:.1} MiB, textures {}/{}, meshes {}/{}",
+            get(&self.bytes) as f64 / (1024. * 1024.),
+            get(&self.textures_done),
+            get(&self.textures_total),
+            get(&self.meshes_done),
+            get(&self.meshes_total),
+        )
+    }
+}
 
 pub struct GltfDocument {
     pub document: gltf::Document,
     pub buffers: Vec<gltf::buffer::Data>,
-    pub images: Vec<gltf::image::Data>,
+    pub images: Vec<Image>,
+    /// File the document was imported from, `None` when loaded from memory
+    pub path: Option<PathBuf>,
 }
 
 impl GltfDocument {
     pub fn import(path: impl AsRef<Path>) -> Result<Self> {
-        let (document, buffers, images) = gltf::import(path)?;
+        Self::import_with_progress(path, &LoadProgress::default())
+    }
+
+    pub fn import_with_progress(path: impl AsRef<Path>, progress: &LoadProgress) -> Result<Self> {
+        let path = path.as_ref();
+        let base = path.parent().unwrap_or(Path::new(""));
+        let data = std::fs::read(path)?;
+        LoadProgress::add(&progress.bytes, data.len());
+        let mut gltf = Self::from_slice_with_progress(&data, &FileResolver::new(base), progress)?;
+        gltf.path = Some(path.to_owned());
+        Ok(gltf)
+    }
+
+    /// Reads the buffers of the document at `path` again, images aren't decoded.
+    pub fn read_buffers(path: impl AsRef<Path>) -> Result<Vec<gltf::buffer::Data>> {
+        let path = path.as_ref();
+        let base = path.parent().unwrap_or(Path::new(""));
+        let data = std::fs::read(path)?;
+        let (document, blob) = parse(&data)?;
+        import_buffers(
+            &document,
+            &FileResolver::new(base),
+            blob,
+            &LoadProgress::default(),
+        )
+    }
+
+    /// Loads glTF or GLB from memory, external URIs are fetched through `resolver`.
+    pub fn from_slice(data: &[u8], resolver: &dyn ResourceResolver) -> Result<Self> {
+        Self::from_slice_with_progress(data, resolver, &LoadProgress::default())
+    }
+
+    pub fn from_slice_with_progress(
+        data: &[u8],
+        resolver: &dyn ResourceResolver,
+        progress: &LoadProgress,
+    ) -> Result<Self> {
+        let (document, blob) = parse(data)?;
+        let buffers = import_buffers(&document, resolver, blob, progress)?;
+        let images = import_images(&document, resolver, &buffers, progress)?;
         Ok(Self {
             document,
             buffers,
             images,
+            path: None,
         })
     }
 
-    pub fn data_of_accessor<'a>(&'a self, accessor: &gltf::Accessor<'a>) -> Option<&'a [u8]> {
-        let buffer_view = accessor.view()?;
-        let buffer = buffer_view.buffer();
-        let buffer_data = &self.buffers[buffer.index()];
-        let buffer_view_data =
-            &buffer_data[buffer_view.offset()..buffer_view.offset() + buffer_view.length()];
-        let accessor_data = &buffer_view_data
-            [accessor.offset()..accessor.offset() + accessor.count() * accessor.size()];
-        Some(accessor_data)
+    pub fn rgba_image(&self, index: usize) -> Result<&RgbaImage> {
+        match self.images.get(index) {
+            Some(Image::Rgba(levels)) => Ok(&levels[0]),
+            Some(Image::Ktx2(_)) => bail!("Image {index} is a KTX2 texture, not RGBA."),
+            Some(Image::Unsupported(err)) => bail!("Image {index} failed to decode: {err}"),
+            None => bail!("Invalid GLTF: Image index {index} out of bounds."),
+        }
+    }
+
+    /// World matrices of all nodes, indexed by node index.
+    pub fn world_transforms(&self) -> Vec<Mat4> {
+        let local = |node: &gltf::Node| Mat4::from_cols_array_2d(&node.transform().matrix());
+        let mut transforms: Vec<_> = self.document.nodes().map(|node| local(&node)).collect();
+        let mut is_child = vec![false; transforms.len()];
+        for child in self.document.nodes().flat_map(|node| node.children()) {
+            is_child[child.index()] = true;
+        }
+
+        let mut stack: Vec<_> = self
+            .document
+            .nodes()
+            .filter(|node| !is_child[node.index()])
+            .collect();
+        while let Some(node) = stack.pop() {
+            let parent = transforms[node.index()];
+            for child in node.children() {
+                transforms[child.index()] = parent * local(&child);
+                stack.push(child);
+            }
+        }
+        transforms
+    }
+
+    /// Per-instance transforms of the `EXT_mesh_gpu_instancing` extension.
+    pub fn instance_transforms(&self, node: &gltf::Node) -> Result<Option<Vec<Mat4>>> {
+        let Some(attributes) = node
+            .extension_value("EXT_mesh_gpu_instancing")
+            .and_then(|ext| ext.get("attributes"))
+        else {
+            return Ok(None);
+        };
+
+        let accessor = |name: &str| {
+            attributes
+                .get(name)
+                .and_then(|index| index.as_u64())
+                .map(|index| {
+                    self.document
+                        .accessors()
+                        .nth(index as usize)
+                        .context("Invalid GLTF: Instancing accessor index out of bounds.")
+                })
+                .transpose()
+        };
+        let translations = accessor("TRANSLATION")?
+            .map(|accessor| self.read_f32(&accessor))
+            .transpose()?;
+        let rotations = accessor("ROTATION")?
+            .map(|accessor| self.read_f32(&accessor))
+            .transpose()?;
+        let scales = accessor("SCALE")?
+            .map(|accessor| self.read_f32(&accessor))
+            .transpose()?;
+
+        let count = [
+            translations.as_ref().map(Vec::len),
+            rotations.as_ref().map(Vec::len),
+            scales.as_ref().map(Vec::len),
+        ]
+        .into_iter()
+        .flatten()
+        .max()
+        .context("Invalid GLTF: EXT_mesh_gpu_instancing without attributes.")?;
+
+        let transforms = (0..count)
+            .map(|i| {
+                Mat4::from_scale_rotation_translation(
+                    nth_or(&scales, i, Vec3::ONE.to_array()).into(),
+                    Quat::from_array(nth_or(&rotations, i, Quat::IDENTITY.to_array())),
+                    nth_or(&translations, i, Vec3::ZERO.to_array()).into(),
+                )
+            })
+            .collect();
+        Ok(Some(transforms))
+    }
+}
+
+fn nth_or<T: Copy>(values: &Option<Vec<T>>, index: usize, default: T) -> T {
+    values
+        .as_ref()
+        .and_then(|values| values.get(index).copied())
+        .unwrap_or(default)
+}
+
+/// Splits GLB into its JSON and blob, extensions wgltf handles pass validation.
+fn parse(data: &[u8]) -> Result<(gltf::Document, Option<Vec<u8>>)> {
+    let (json, blob) = if data.starts_with(b"glTF") {
+        let glb = gltf::binary::Glb::from_slice(data)?;
+        (glb.json, glb.bin.map(Cow::into_owned))
+    } else {
+        (Cow::Borrowed(data), None)
+    };
+    let mut json: gltf::json::Value = gltf::json::deserialize::from_slice(&json)?;
+    patch_texture_sources(&mut json);
+    let mut json: gltf::json::Root = gltf::json::deserialize::from_value(json)?;
+
+    // `gltf` rejects required extensions it doesn't know about
+    json.extensions_required
+        .retain(|extension| !SUPPORTED_EXTENSIONS.contains(&extension.as_str()));
+    let document = gltf::Document::from_json(json)?;
+    Ok((document, blob))
+}
+
+/// Like [`gltf::import_buffers`], but fallback buffers of `EXT_meshopt_compression`
+/// are allocated empty and filled with the decompressed buffer views.
+fn import_buffers(
+    document: &gltf::Document,
+    resolver: &dyn ResourceResolver,
+    mut blob: Option<Vec<u8>>,
+    progress: &LoadProgress,
+) -> Result<Vec<gltf::buffer::Data>> {
+    let mut buffers = Vec::new();
+    for buffer in document.buffers() {
+        let mut data = match buffer.source() {
+            _ if meshopt::is_fallback_buffer(&buffer) => vec![0; buffer.length()],
+            gltf::buffer::Source::Bin => blob.take().context("Invalid GLTF: Missing GLB blob.")?,
+            gltf::buffer::Source::Uri(uri) => {
+                let data = resolver::read_uri(resolver, uri)?;
+                LoadProgress::add(&progress.bytes, data.len());
+                data
+            }
+        };
+        // accessors may read up to 4 byte alignment
+        data.resize(data.len().next_multiple_of(4), 0);
+        if data.len() < buffer.length() {
+            bail!(
+                "Invalid GLTF: Buffer {} has {} bytes, expected {}.",
+                buffer.index(),
+                data.len(),
+                buffer.length()
+            );
+        }
+        buffers.push(gltf::buffer::Data(data));
+    }
+    meshopt::decompress_buffer_views(document, &mut buffers)?;
+    Ok(buffers)
+}
+
+/// Image indices of the texture, extension sources first and the core `source` last.
+pub fn texture_sources(texture: &gltf::Texture) -> Vec<usize> {
+    let mut sources = vec![];
+    let extension_sources = TEXTURE_SOURCE_EXTENSIONS.iter().filter_map(|extension| {
+        let source = texture
+            .extension_value(extension)?
+            .get("source")?
+            .as_u64()?;
+        Some(source as usize)
+    });
+    for source in extension_sources.chain([texture.source().index()]) {
+        if !sources.contains(&source) {
+            sources.push(source);
+        }
+    }
+    sources
+}
+
+/// Texture extensions allow omitting the core `source`, which `gltf` requires.
+fn patch_texture_sources(json: &mut gltf::json::Value) {
+    let Some(textures) = json.get_mut("textures").and_then(|t| t.as_array_mut()) else {
+        return;
+    };
+    for texture in textures {
+        if texture.get("source").is_some() {
+            continue;
+        }
+        let source = TEXTURE_SOURCE_EXTENSIONS.iter().find_map(|extension| {
+            texture
+                .get("extensions")?
+                .get(extension)?
+                .get("source")
+                .cloned()
+        });
+        if let (Some(source), Some(texture)) = (source, texture.as_object_mut()) {
+            texture.insert("source".to_owned(), source);
+        }
+    }
+}
+
+/// Decodes images into RGBA mip chains, KTX2 containers are kept for direct upload.
+fn import_images(
+    document: &gltf::Document,
+    resolver: &dyn ResourceResolver,
+    buffers: &[gltf::buffer::Data],
+    progress: &LoadProgress,
+) -> Result<Vec<Image>> {
+    LoadProgress::add(&progress.textures_total, document.images().len());
+    let images: Vec<_> = document.images().collect();
+    par_map(&images, |gltf_image| {
+        let data = match gltf_image.source() {
+            gltf::image::Source::View { view, .. } => Cow::Borrowed(
+                buffers[view.buffer().index()]
+                    .get(view.offset()..view.offset() + view.length())
+                    .context("Invalid GLTF: Image view out of buffer bounds.")?,
+            ),
+            gltf::image::Source::Uri { uri, .. } => {
+                let data = resolver::read_uri(resolver, uri)?;
+                LoadProgress::add(&progress.bytes, data.len());
+                Cow::Owned(data)
+            }
+        };
+        let image = if Ktx2::is_ktx2(&data) {
+            Ktx2::parse(&data).map(Image::Ktx2)
+        } else {
+            image::load_from_memory(&data)
+                .map(|image| Image::Rgba(generate_mips(image.into_rgba8())))
+                .map_err(Into::into)
+        };
+        LoadProgress::add(&progress.textures_done, 1);
+        Ok(image.unwrap_or_else(|err| Image::Unsupported(err.to_string())))
+    })
+    .into_iter()
+    .collect()
+}
+
+/// Halves the image down to 1x1, the image itself is the first level.
+pub fn generate_mips(image: RgbaImage) -> Vec<RgbaImage> {
+    let mut levels = vec![image];
+    loop {
+        let (width, height) = levels.last().unwrap().dimensions();
+        if width == 1 && height == 1 {
+            break levels;
+        }
+        let level = image::imageops::resize(
+            levels.last().unwrap(),
+            (width / 2).max(1),
+            (height / 2).max(1),
+            FilterType::Triangle,
+        );
+        levels.push(level);
     }
 }
@@ -0,0 +1,54 @@
+use std::path::{Path, PathBuf};
+
+use color_eyre::{eyre::bail, Result};
+
+/// Fetches external resources (`.bin` buffers and images) referenced by URI.
+/// `data:` URIs are decoded before reaching the resolver. Images are fetched from several
+/// threads at once.
+pub trait ResourceResolver: Sync {
+    fn resolve(&self, uri: &str) -> Result<Vec<u8>>;
+}
+
+impl<F: Fn(&str) -> Result<Vec<u8>> + Sync> ResourceResolver for F {
+    fn resolve(&self, uri: &str) -> Result<Vec<u8>> {
+        self(uri)
+    }
+}
+
+/// Resolves relative and `file:` URIs on the filesystem.
+#[derive(Debug, Clone)]
+pub struct FileResolver {
+    pub base: PathBuf,
+}
+
+impl FileResolver {
+    pub fn new(base: impl Into<PathBuf>) -> Self {
+        Self { base: base.into() }
+    }
+}
+
+impl ResourceResolver for FileResolver {
+    fn resolve(&self, uri: &str) -> Result<Vec<u8>> {
+        let source = gltf::buffer::Source::Uri(uri);
+        Ok(gltf::buffer::Data::from_source(source, Some(&self.base))?.0)
+    }
+}
+
+/// Rejects every external reference, for self-contained GLB or embedded glTF.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoResolver;
+
+impl ResourceResolver for NoResolver {
+    fn resolve(&self, uri: &str) -> Result<Vec<u8>> {
+        bail!("External reference {uri:?} without a resource resolver.")
+    }
+}
+
+pub(crate) fn read_uri(resolver: &dyn ResourceResolver, uri: &str) -> Result<Vec<u8>> {
+    if uri.starts_with("data:") {
+        let source = gltf::buffer::Source::Uri(uri);
+        Ok(gltf::buffer::Data::from_source(source, None::<&Path>)?.0)
+    } else {
+        resolver.resolve(uri)
+    }
+}
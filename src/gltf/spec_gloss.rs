@@ -0,0 +1,220 @@
+use color_eyre::Result;
+use glam::{vec3, Vec3};
+use image::{imageops::FilterType, Rgba, RgbaImage};
+
+use super::GltfDocument;
+
+const DIELECTRIC_SPECULAR: f32 = 0.04;
+const EPSILON: f32 = 1e-6;
+
+/// Metallic-roughness parameters baked from a `KHR_materials_pbrSpecularGlossiness` material.
+/// Baked images already include the factors, so those are reset to one.
+pub struct ConvertedMaterial {
+    pub base_color_factor: [f32; 4],
+    pub metallic_factor: f32,
+    pub roughness_factor: f32,
+    pub base_color_image: Option<RgbaImage>,
+    pub metallic_roughness_image: Option<RgbaImage>,
+    /// Source texture the baked images borrow their sampler from
+    pub texture: Option<usize>,
+}
+
+/// Converts spec-gloss material into metallic-roughness following the Khronos reference converter.
+pub fn convert_specular_glossiness(
+    gltf: &GltfDocument,
+    spec_gloss: &gltf::material::PbrSpecularGlossiness,
+) -> Result<ConvertedMaterial> {
+    let diffuse_factor = spec_gloss.diffuse_factor();
+    let specular_factor = Vec3::from(spec_gloss.specular_factor());
+    let glossiness_factor = spec_gloss.glossiness_factor();
+    let diffuse_texture = spec_gloss.diffuse_texture().map(|t| t.texture());
+    let spec_gloss_texture = spec_gloss
+        .specular_glossiness_texture()
+        .map(|t| t.texture());
+    let texture = diffuse_texture.as_ref().or(spec_gloss_texture.as_ref());
+
+    let Some(texture) = texture.map(|t| t.index()) else {
+        let [r, g, b, a] = diffuse_factor;
+        let (base_color, metallic) = convert_texel(vec3(r, g, b), specular_factor);
+        let [r, g, b] = base_color.to_array();
+        return Ok(ConvertedMaterial {
+            base_color_factor: [r, g, b, a],
+            metallic_factor: metallic,
+            roughness_factor: 1. - glossiness_factor,
+            base_color_image: None,
+            metallic_roughness_image: None,
+            texture: None,
+        });
+    };
+
+    let load = |texture: Option<gltf::Texture>| {
+        texture
+            .map(|t| gltf.rgba_image(t.source().index()).cloned())
+            .transpose()
+    };
+    let diffuse = load(diffuse_texture)?;
+    let spec_gloss = load(spec_gloss_texture)?;
+    let (width, height) = match (&diffuse, &spec_gloss) {
+        (Some(image), _) | (None, Some(image)) => image.dimensions(),
+        (None, None) => unreachable!(),
+    };
+    // Both maps are sampled texel by texel, so bring them to the same resolution
+    let spec_gloss = spec_gloss.map(|image| {
+        if image.dimensions() == (width, height) {
+            image
+        } else {
+            image::imageops::resize(&image, width, height, FilterType::Triangle)
+        }
+    });
+
+    let mut base_color_image = RgbaImage::new(width, height);
+    let mut metallic_roughness_image = RgbaImage::new(width, height);
+    for (x, y, base_color_texel) in base_color_image.enumerate_pixels_mut() {
+        let [dr, dg, db, da] = diffuse.as_ref().map_or([255; 4], |i| i.get_pixel(x, y).0);
+        let [sr, sg, sb, gloss] = spec_gloss
+            .as_ref()
+            .map_or([255; 4], |i| i.get_pixel(x, y).0);
+
+        let diffuse = vec3(srgb_to_linear(dr), srgb_to_linear(dg), srgb_to_linear(db))
+            * Vec3::from_slice(&diffuse_factor[..3]);
+        let specular =
+            vec3(srgb_to_linear(sr), srgb_to_linear(sg), srgb_to_linear(sb)) * specular_factor;
+        let alpha = da as f32 / 255. * diffuse_factor[3];
+        let roughness = 1. - gloss as f32 / 255. * glossiness_factor;
+
+        let (base_color, metallic) = convert_texel(diffuse, specular);
+        *base_color_texel = Rgba([
+            linear_to_srgb(base_color.x),
+            linear_to_srgb(base_color.y),
+            linear_to_srgb(base_color.z),
+            unorm(alpha),
+        ]);
+        metallic_roughness_image.put_pixel(x, y, Rgba([0, unorm(roughness), unorm(metallic), 255]));
+    }
+
+    Ok(ConvertedMaterial {
+        base_color_factor: [1.; 4],
+        metallic_factor: 1.,
+        roughness_factor: 1.,
+        base_color_image: Some(base_color_image),
+        metallic_roughness_image: Some(metallic_roughness_image),
+        texture: Some(texture),
+    })
+}
+
+/// Returns linear base color and metallic for linear diffuse and specular colors.
+fn convert_texel(diffuse: Vec3, specular: Vec3) -> (Vec3, f32) {
+    let one_minus_specular_strength = 1. - specular.max_element();
+    let metallic = solve_metallic(
+        perceived_brightness(diffuse),
+        perceived_brightness(specular),
+        one_minus_specular_strength,
+    );
+
+    let base_color_from_diffuse = diffuse
+        * (one_minus_specular_strength / (1. - DIELECTRIC_SPECULAR) / (1. - metallic).max(EPSILON));
+    let base_color_from_specular =
+        (specular - Vec3::splat(DIELECTRIC_SPECULAR * (1. - metallic))) / metallic.max(EPSILON);
+    let base_color = base_color_from_diffuse
+        .lerp(base_color_from_specular, metallic * metallic)
+        .clamp(Vec3::ZERO, Vec3::ONE);
+
+    (base_color, metallic)
+}
+
+fn solve_metallic(diffuse: f32, specular: f32, one_minus_specular_strength: f32) -> f32 {
+    if specular < DIELECTRIC_SPECULAR {
+        return 0.;
+    }
+
+    let a = DIELECTRIC_SPECULAR;
+    let b = diffuse * one_minus_specular_strength / (1. - DIELECTRIC_SPECULAR) + specular
+        - 2. * DIELECTRIC_SPECULAR;
+    let c = DIELECTRIC_SPECULAR - specular;
+    let discriminant = (b * b - 4. * a * c).max(0.);
+    ((-b + discriminant.sqrt()) / (2. * a)).clamp(0., 1.)
+}
+
+fn perceived_brightness(color: Vec3) -> f32 {
+    (0.299 * color.x * color.x + 0.587 * color.y * color.y + 0.114 * color.z * color.z).sqrt()
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let value = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1. / 2.4) - 0.055
+    };
+    unorm(value)
+}
+
+fn unorm(value: f32) -> u8 {
+    (value.clamp(0., 1.) * 255.).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Spec-gloss colors the metallic-roughness result renders as.
+    fn to_spec_gloss(base_color: Vec3, metallic: f32) -> (Vec3, Vec3) {
+        let diffuse = base_color * (1. - DIELECTRIC_SPECULAR) * (1. - metallic);
+        let specular = Vec3::splat(DIELECTRIC_SPECULAR).lerp(base_color, metallic);
+        (diffuse, specular)
+    }
+
+    #[test]
+    fn pure_dielectric() {
+        let diffuse = vec3(0.5, 0.2, 0.1);
+        assert_eq!(solve_metallic(0.5, 0.02, 0.98), 0.);
+
+        let (base_color, metallic) = convert_texel(diffuse, Vec3::splat(DIELECTRIC_SPECULAR));
+        assert!(metallic < 1e-3, "metallic {metallic}");
+        assert!(
+            base_color.abs_diff_eq(diffuse, 1e-3),
+            "base color {base_color}"
+        );
+    }
+
+    #[test]
+    fn pure_metal() {
+        let gold = vec3(1., 0.766, 0.336);
+        assert!(solve_metallic(0., 1., 0.) > 0.999);
+
+        let (base_color, metallic) = convert_texel(Vec3::ZERO, gold);
+        assert!(metallic > 0.999, "metallic {metallic}");
+        assert!(
+            base_color.abs_diff_eq(gold, 1e-3),
+            "base color {base_color}"
+        );
+    }
+
+    #[test]
+    fn energy_conservation() {
+        let steps = (0..=10).map(|step| step as f32 / 10.);
+        for diffuse in steps.clone() {
+            for specular in steps.clone().filter(|specular| diffuse + specular <= 1.) {
+                let (base_color, metallic) =
+                    convert_texel(Vec3::splat(diffuse), Vec3::splat(specular));
+                assert!((0. ..=1.).contains(&metallic));
+                assert!(base_color.cmpge(Vec3::ZERO).all() && base_color.cmple(Vec3::ONE).all());
+
+                let (diffuse, specular) = to_spec_gloss(base_color, metallic);
+                let reflected = (diffuse + specular).max_element();
+                assert!(
+                    reflected <= 1. + 1e-3,
+                    "{diffuse} + {specular} reflects {reflected}"
+                );
+            }
+        }
+    }
+}
@@ -2,4 +2,5 @@ pub mod app;
 pub mod camera;
 pub mod gltf;
 pub mod input;
+pub mod mesh;
 pub mod utils;
@@ -2,30 +2,33 @@ use std::time::Instant;
 
 use color_eyre::Result;
 use glam::vec3;
-use log::warn;
+use log::{error, info, warn};
 use wgltf::{
-    app::{App, AppState},
+    app::{App, AppState, CullingMode, ModelLoader, TransparencyMode},
     camera::Camera,
-    gltf::GltfDocument,
     input::{KeyMap, KeyboardMap},
 };
 use wgpu::SurfaceError;
 use winit::{
     dpi::{LogicalSize, PhysicalSize},
-    event::{Event, KeyboardInput, VirtualKeyCode, WindowEvent},
+    event::{ElementState, Event, KeyboardInput, VirtualKeyCode, WindowEvent},
     event_loop::ControlFlow,
 };
 
 const UPDATES_PER_SECOND: u32 = 60;
 const FIXED_TIME_STEP: f64 = 1. / UPDATES_PER_SECOND as f64;
 const MAX_FRAME_TIME: f64 = 15. * FIXED_TIME_STEP; // 0.25;
+const TITLE: &str = "Poisson Corrode";
+/// Written on F5 with the edits made to the loaded model
+const EXPORT_PATH: &str = "export.glb";
 
 fn main() -> Result<()> {
     color_eyre::install()?;
+    env_logger::init();
 
     let event_loop = winit::event_loop::EventLoop::new();
     let window = winit::window::WindowBuilder::new()
-        .with_title("Poisson Corrode")
+        .with_title(TITLE)
         .with_inner_size(LogicalSize::new(1280, 1024))
         .build(&event_loop)?;
 
@@ -48,14 +51,18 @@ fn main() -> Result<()> {
     let info = app.get_info();
     println!("{info}");
 
-    let scene = GltfDocument::import(
-        "assets/sponza-optimized/Sponza.gltf",
-        // "assets/glTF-Sample-Models/2.0/AntiqueCamera/glTF/AntiqueCamera.gltf",
-        // "assets/glTF-Sample-Models/2.0/Buggy/glTF-Binary/Buggy.glb",
-        // "assets/glTF-Sample-Models/2.0/FlightHelmet/glTF/FlightHelmet.gltf",
-        // "assets/glTF-Sample-Models/2.0/DamagedHelmet/glTF-Binary/DamagedHelmet.glb",
-    )?;
-    app.add_gltf_model(scene)?;
+    // Decoding and vertex conversion happen on a worker, the window keeps rendering meanwhile.
+    // Takes glTF, GLB, OBJ, STL or PLY as the first argument:
+    // assets/glTF-Sample-Models/2.0/AntiqueCamera/glTF/AntiqueCamera.gltf
+    // assets/glTF-Sample-Models/2.0/Buggy/glTF-Binary/Buggy.glb
+    // assets/glTF-Sample-Models/2.0/FlightHelmet/glTF/FlightHelmet.gltf
+    // assets/glTF-Sample-Models/2.0/DamagedHelmet/glTF-Binary/DamagedHelmet.glb
+    // assets/cube/cube.obj
+    let path = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "assets/sponza-optimized/Sponza.gltf".to_owned());
+    let mut loader = Some(ModelLoader::spawn(path));
+    let mut model_id = None;
 
     let mut current_instant = Instant::now();
     let mut accumulated_time = 0.;
@@ -66,6 +73,25 @@ fn main() -> Result<()> {
         app_state.input.update(&event, &window);
         match event {
             Event::MainEventsCleared => {
+                if let Some(model_loader) = &loader {
+                    match model_loader.try_finish() {
+                        None => window
+                            .set_title(&format!("{TITLE} - Loading {}", model_loader.progress())),
+                        Some(model) => {
+                            let path = &model_loader.path;
+                            match model.and_then(|model| app.add_loaded_model(model)) {
+                                Ok(id) => {
+                                    info!("Loaded {path:?}");
+                                    model_id = Some(id);
+                                }
+                                Err(err) => error!("Failed to load {path:?}: {err:?}"),
+                            }
+                            window.set_title(TITLE);
+                            loader = None;
+                        }
+                    }
+                }
+
                 let new_instant = Instant::now();
                 let frame_time = new_instant
                     .duration_since(current_instant)
@@ -104,11 +130,9 @@ fn main() -> Result<()> {
                         ..
                     },
                 ..
-            } => {
-                if width != 0 && height != 0 {
-                    app_state.camera.aspect = width as f32 / height as f32;
-                    app.resize(width, height);
-                }
+            } if width != 0 && height != 0 => {
+                app_state.camera.aspect = width as f32 / height as f32;
+                app.resize(width, height);
             }
             Event::WindowEvent {
                 event:
@@ -123,6 +147,84 @@ fn main() -> Result<()> {
                     },
                 ..
             } => *control_flow = ControlFlow::Exit,
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F5),
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => {
+                if let Some(model_id) = model_id {
+                    match app.export_model(model_id, EXPORT_PATH) {
+                        Ok(()) => info!("Exported to {EXPORT_PATH:?}"),
+                        Err(err) => error!("Failed to export to {EXPORT_PATH:?}: {err:?}"),
+                    }
+                }
+            }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F6),
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => {
+                let enabled = !app.alpha_to_coverage();
+                app.set_alpha_to_coverage(enabled);
+                info!("Alpha to coverage: {enabled}");
+            }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F7),
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => {
+                let mode = match app.transparency_mode() {
+                    TransparencyMode::Sorted => TransparencyMode::WeightedBlended,
+                    TransparencyMode::WeightedBlended => TransparencyMode::Sorted,
+                };
+                app.set_transparency_mode(mode);
+                info!("Transparency: {mode:?}");
+            }
+            Event::WindowEvent {
+                event:
+                    WindowEvent::KeyboardInput {
+                        input:
+                            KeyboardInput {
+                                state: ElementState::Pressed,
+                                virtual_keycode: Some(VirtualKeyCode::F8),
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => {
+                let mode = match app.culling_mode() {
+                    CullingMode::Cpu => CullingMode::Gpu,
+                    CullingMode::Gpu => CullingMode::GpuOcclusion,
+                    CullingMode::GpuOcclusion => CullingMode::Cpu,
+                };
+                app.set_culling_mode(mode);
+                info!("Culling: {:?}", app.culling_mode());
+            }
             Event::LoopDestroyed => {
                 println!("// End from the loop. Bye bye~⏎ ");
             }
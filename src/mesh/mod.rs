@@ -0,0 +1,79 @@
+//! Formats without a scene graph, every mesh is drawn once with an identity transform.
+
+use std::path::Path;
+
+use color_eyre::{eyre::bail, Result};
+use gltf::material::AlphaMode;
+use image::RgbaImage;
+
+use crate::app::{Indices, MeshVertex};
+
+mod obj;
+mod ply;
+mod stl;
+
+/// File extensions handled by [`MeshDocument::import`].
+pub const SUPPORTED_EXTENSIONS: &[&str] = &["obj", "ply", "stl"];
+
+/// Material in metallic-roughness terms, images with their mip chains.
+pub struct MeshMaterial {
+    pub name: String,
+    pub base_color_factor: [f32; 3],
+    pub roughness_factor: f32,
+    /// `map_Kd` with `map_d` and `d` baked into its alpha channel
+    pub base_color_mips: Option<Vec<RgbaImage>>,
+    /// `map_Bump` or `bump`, read as a tangent space normal map
+    pub normal_mips: Option<Vec<RgbaImage>>,
+    pub alpha_mode: AlphaMode,
+}
+
+pub struct Mesh {
+    pub name: String,
+    pub vertices: Vec<MeshVertex>,
+    pub indices: Option<Indices>,
+    pub topology: wgpu::PrimitiveTopology,
+    /// Meshes without one use the default material
+    pub material: Option<usize>,
+}
+
+pub struct MeshDocument {
+    pub meshes: Vec<Mesh>,
+    pub materials: Vec<MeshMaterial>,
+}
+
+impl MeshDocument {
+    pub fn is_supported(path: &Path) -> bool {
+        extension(path).is_some_and(|extension| SUPPORTED_EXTENSIONS.contains(&extension.as_str()))
+    }
+
+    /// Picks the format by file extension.
+    pub fn import(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        match extension(path).as_deref() {
+            Some("obj") => obj::import(path),
+            Some("ply") => ply::import(path),
+            Some("stl") => stl::import(path),
+            _ => bail!("Unsupported mesh format of {path:?}."),
+        }
+    }
+}
+
+fn extension(path: &Path) -> Option<String> {
+    Some(path.extension()?.to_str()?.to_ascii_lowercase())
+}
+
+/// Area weighted vertex normals for triangle lists without them.
+fn compute_normals(vertices: &mut [MeshVertex], indices: &[u32]) {
+    let position = |index: u32| glam::Vec3::from(vertices[index as usize].position);
+    let mut normals = vec![glam::Vec3::ZERO; vertices.len()];
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]];
+        let normal = (position(b) - position(a)).cross(position(c) - position(a));
+        for index in triangle {
+            normals[*index as usize] += normal;
+        }
+    }
+    for (vertex, normal) in vertices.iter_mut().zip(normals) {
+        vertex.normal = normal.normalize_or_zero().into();
+    }
+}
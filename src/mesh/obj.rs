@@ -0,0 +1,180 @@
+//! Wavefront OBJ, MTL materials are mapped onto the glTF material slots.
+
+use std::{iter::zip, path::Path};
+
+use color_eyre::{eyre::Context, Result};
+use gltf::material::AlphaMode;
+use image::{imageops::FilterType, DynamicImage, GrayImage, Rgba, RgbaImage};
+use log::warn;
+
+use super::{compute_normals, Mesh, MeshDocument, MeshMaterial};
+use crate::{
+    app::{Indices, MeshVertex},
+    gltf::generate_mips,
+    utils::par_map,
+};
+
+pub fn import(path: &Path) -> Result<MeshDocument> {
+    let base = path.parent().unwrap_or(Path::new(""));
+    let source =
+        std::fs::read_to_string(path).with_context(|| format!("Failed to read {path:?}"))?;
+    let (models, materials) = tobj::load_obj_buf(
+        &mut strip_vertex_colors(&source).as_bytes(),
+        &tobj::GPU_LOAD_OPTIONS,
+        |mtl| tobj::load_mtl(base.join(mtl)),
+    )
+    .with_context(|| format!("Failed to load {path:?}"))?;
+    let materials = materials.unwrap_or_else(|err| {
+        warn!("Using the default material for {path:?}: {err}");
+        vec![]
+    });
+
+    let materials = par_map(&materials, |material| convert_material(base, material));
+    let meshes = par_map(&models, |model| {
+        let mesh = &model.mesh;
+        let mut vertices: Vec<_> = mesh
+            .positions
+            .chunks_exact(3)
+            .enumerate()
+            .map(|(index, position)| MeshVertex {
+                position: [position[0], position[1], position[2]],
+                normal: mesh
+                    .normals
+                    .get(index * 3..index * 3 + 3)
+                    .map_or([0.; 3], |n| [n[0], n[1], n[2]]),
+                // OBJ puts the texture origin at the bottom left
+                tex_coord: mesh
+                    .texcoords
+                    .get(index * 2..index * 2 + 2)
+                    .map_or([0.; 2], |t| [t[0], 1. - t[1]]),
+                color: [1.; 4],
+            })
+            .collect();
+        if mesh.normals.is_empty() {
+            compute_normals(&mut vertices, &mesh.indices);
+        }
+        Mesh {
+            name: model.name.clone(),
+            vertices,
+            indices: Some(Indices::U32(mesh.indices.clone())),
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            material: mesh.material_id.filter(|&id| id < materials.len()),
+        }
+    });
+    Ok(MeshDocument { meshes, materials })
+}
+
+/// Vertex colors are unused, and `tobj` rejects files that give them to only some vertices.
+fn strip_vertex_colors(source: &str) -> String {
+    let mut stripped = String::with_capacity(source.len());
+    for line in source.lines() {
+        match line.strip_prefix("v ") {
+            Some(position) => {
+                stripped.push_str("v ");
+                let xyz: Vec<_> = position.split_whitespace().take(3).collect();
+                stripped.push_str(&xyz.join(" "));
+            }
+            None => stripped.push_str(line),
+        }
+        stripped.push('\n');
+    }
+    stripped
+}
+
+/// Textures that fail to load are left out of the material.
+fn convert_material(base: &Path, material: &tobj::Material) -> MeshMaterial {
+    let load = |file: &str| {
+        if file.is_empty() {
+            return None;
+        }
+        // Exporters on Windows write backslashes
+        let path = base.join(file.replace('\\', "/"));
+        image::open(&path)
+            .map_err(|err| warn!("Skipping {path:?} of material {:?}: {err}", material.name))
+            .ok()
+    };
+    let diffuse = load(&material.diffuse_texture).map(DynamicImage::into_rgba8);
+    let alpha = load(&material.dissolve_texture).map(|image| image.to_luma8());
+    let normal = load(&material.normal_texture).map(DynamicImage::into_rgba8);
+
+    let alpha_mode = if alpha.is_some() {
+        AlphaMode::Mask
+    } else if material.dissolve < 1. {
+        AlphaMode::Blend
+    } else {
+        AlphaMode::Opaque
+    };
+    let base_color = match alpha_mode {
+        AlphaMode::Opaque => diffuse,
+        _ => Some(bake_alpha(diffuse, alpha, material.dissolve)),
+    };
+
+    MeshMaterial {
+        name: material.name.clone(),
+        base_color_factor: material.diffuse,
+        // Blinn-Phong exponent to GGX roughness
+        roughness_factor: (2. / (material.shininess + 2.)).sqrt(),
+        base_color_mips: base_color.map(generate_mips),
+        normal_mips: normal.map(generate_mips),
+        alpha_mode,
+    }
+}
+
+fn bake_alpha(diffuse: Option<RgbaImage>, alpha: Option<GrayImage>, dissolve: f32) -> RgbaImage {
+    let (width, height) = match (&diffuse, &alpha) {
+        (Some(image), _) => image.dimensions(),
+        (None, Some(alpha)) => alpha.dimensions(),
+        (None, None) => (1, 1),
+    };
+    let mut image = diffuse.unwrap_or_else(|| RgbaImage::from_pixel(width, height, Rgba([255; 4])));
+    let alpha = alpha.map(|alpha| {
+        if alpha.dimensions() == (width, height) {
+            alpha
+        } else {
+            image::imageops::resize(&alpha, width, height, FilterType::Triangle)
+        }
+    });
+    match alpha {
+        Some(alpha) => {
+            for (texel, alpha) in zip(image.pixels_mut(), alpha.pixels()) {
+                texel[3] = (alpha[0] as f32 * dissolve) as u8;
+            }
+        }
+        None => {
+            for texel in image.pixels_mut() {
+                texel[3] = (texel[3] as f32 * dissolve) as u8;
+            }
+        }
+    }
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_texture_and_normals() {
+        let dir = std::env::temp_dir().join("wgltf_obj_missing_texture_and_normals");
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(
+            dir.join("quad.mtl"),
+            "newmtl red\nKd 1 0 0\nmap_Kd missing.png\n",
+        )
+        .unwrap();
+        std::fs::write(
+            dir.join("quad.obj"),
+            "mtllib quad.mtl\nv 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\nusemtl red\nf 1 2 3 4\n",
+        )
+        .unwrap();
+
+        let document = import(&dir.join("quad.obj")).unwrap();
+        assert_eq!(document.materials.len(), 1);
+        assert!(document.materials[0].base_color_mips.is_none());
+        let mesh = &document.meshes[0];
+        assert_eq!(mesh.material, Some(0));
+        for vertex in &mesh.vertices {
+            assert_eq!(vertex.normal, [0., 0., 1.]);
+        }
+    }
+}
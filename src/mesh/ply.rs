@@ -0,0 +1,451 @@
+//! PLY in ASCII and binary encodings. Vertex normals, colors and texture coordinates are
+//! read when present, files without faces become point lists.
+
+use std::path::Path;
+
+use color_eyre::{
+    eyre::{bail, ensure, Context, ContextCompat},
+    Result,
+};
+
+use super::{compute_normals, Mesh, MeshDocument};
+use crate::app::{Indices, MeshVertex};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Scalar {
+    I8,
+    U8,
+    I16,
+    U16,
+    I32,
+    U32,
+    F32,
+    F64,
+}
+
+impl Scalar {
+    fn parse(name: &str) -> Result<Self> {
+        let scalar = match name {
+            "char" | "int8" => Self::I8,
+            "uchar" | "uint8" => Self::U8,
+            "short" | "int16" => Self::I16,
+            "ushort" | "uint16" => Self::U16,
+            "int" | "int32" => Self::I32,
+            "uint" | "uint32" => Self::U32,
+            "float" | "float32" => Self::F32,
+            "double" | "float64" => Self::F64,
+            _ => bail!("PLY: Unknown property type {name:?}."),
+        };
+        Ok(scalar)
+    }
+
+    fn size(self) -> usize {
+        match self {
+            Self::I8 | Self::U8 => 1,
+            Self::I16 | Self::U16 => 2,
+            Self::I32 | Self::U32 | Self::F32 => 4,
+            Self::F64 => 8,
+        }
+    }
+
+    /// Color channel in `0..=1`, integer colors span their whole range.
+    fn normalize(self, value: f64) -> f32 {
+        let max = match self {
+            Self::U8 => u8::MAX as f64,
+            Self::U16 => u16::MAX as f64,
+            Self::U32 => u32::MAX as f64,
+            Self::I8 => i8::MAX as f64,
+            Self::I16 => i16::MAX as f64,
+            Self::I32 => i32::MAX as f64,
+            Self::F32 | Self::F64 => 1.,
+        };
+        (value / max) as f32
+    }
+}
+
+#[derive(Debug)]
+enum Property {
+    Scalar {
+        name: String,
+        ty: Scalar,
+    },
+    List {
+        name: String,
+        count: Scalar,
+        item: Scalar,
+    },
+}
+
+#[derive(Debug)]
+struct Element {
+    name: String,
+    count: usize,
+    properties: Vec<Property>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Encoding {
+    Ascii,
+    LittleEndian,
+    BigEndian,
+}
+
+/// Elements of the header and the offset of the body.
+fn parse_header(data: &[u8]) -> Result<(Encoding, Vec<Element>, usize)> {
+    ensure!(data.starts_with(b"ply"), "PLY: Missing magic number.");
+    let mut encoding = None;
+    let mut elements: Vec<Element> = vec![];
+    let mut offset = 0;
+    loop {
+        let end = data[offset..]
+            .iter()
+            .position(|&byte| byte == b'\n')
+            .context("PLY: Truncated header.")?;
+        let line = std::str::from_utf8(&data[offset..offset + end])?.trim();
+        offset += end + 1;
+
+        let mut words = line.split_ascii_whitespace();
+        match words.next() {
+            Some("format") => {
+                encoding = Some(match words.next() {
+                    Some("ascii") => Encoding::Ascii,
+                    Some("binary_little_endian") => Encoding::LittleEndian,
+                    Some("binary_big_endian") => Encoding::BigEndian,
+                    format => bail!("PLY: Unknown format {format:?}."),
+                });
+            }
+            Some("element") => {
+                let (Some(name), Some(count)) = (words.next(), words.next()) else {
+                    bail!("PLY: Invalid element {line:?}.");
+                };
+                elements.push(Element {
+                    name: name.to_owned(),
+                    count: count.parse()?,
+                    properties: vec![],
+                });
+            }
+            Some("property") => {
+                let element = elements
+                    .last_mut()
+                    .context("PLY: Property outside of an element.")?;
+                let words: Vec<_> = words.collect();
+                let property = match words[..] {
+                    ["list", count, item, name] => Property::List {
+                        name: name.to_owned(),
+                        count: Scalar::parse(count)?,
+                        item: Scalar::parse(item)?,
+                    },
+                    [ty, name] => Property::Scalar {
+                        name: name.to_owned(),
+                        ty: Scalar::parse(ty)?,
+                    },
+                    _ => bail!("PLY: Invalid property {line:?}."),
+                };
+                element.properties.push(property);
+            }
+            Some("end_header") => break,
+            // `comment`, `obj_info` and blank lines
+            _ => {}
+        }
+    }
+    let encoding = encoding.context("PLY: Missing format.")?;
+    Ok((encoding, elements, offset))
+}
+
+/// Values of the body, read one after another.
+enum Body<'a> {
+    Ascii(std::str::SplitAsciiWhitespace<'a>),
+    Binary { data: &'a [u8], big_endian: bool },
+}
+
+impl Body<'_> {
+    fn read(&mut self, ty: Scalar) -> Result<f64> {
+        match self {
+            Self::Ascii(words) => Ok(words.next().context("PLY: Truncated body.")?.parse()?),
+            Self::Binary { data, big_endian } => {
+                ensure!(data.len() >= ty.size(), "PLY: Truncated body.");
+                let (bytes, rest) = data.split_at(ty.size());
+                *data = rest;
+                macro_rules! read {
+                    ($ty:ty) => {{
+                        let bytes = bytes.try_into().unwrap();
+                        if *big_endian {
+                            <$ty>::from_be_bytes(bytes) as f64
+                        } else {
+                            <$ty>::from_le_bytes(bytes) as f64
+                        }
+                    }};
+                }
+                Ok(match ty {
+                    Scalar::I8 => read!(i8),
+                    Scalar::U8 => read!(u8),
+                    Scalar::I16 => read!(i16),
+                    Scalar::U16 => read!(u16),
+                    Scalar::I32 => read!(i32),
+                    Scalar::U32 => read!(u32),
+                    Scalar::F32 => read!(f32),
+                    Scalar::F64 => read!(f64),
+                })
+            }
+        }
+    }
+
+    fn read_list(&mut self, count: Scalar, item: Scalar) -> Result<Vec<f64>> {
+        let count = self.read(count)? as usize;
+        (0..count).map(|_| self.read(item)).collect()
+    }
+
+    fn skip(&mut self, property: &Property) -> Result<()> {
+        match property {
+            Property::Scalar { ty, .. } => self.read(*ty).map(drop),
+            Property::List { count, item, .. } => self.read_list(*count, *item).map(drop),
+        }
+    }
+}
+
+/// Indices are read like every other value, float and negative ones are invalid.
+fn face_index(value: f64) -> Result<u32> {
+    if value < 0. || value > u32::MAX as f64 || value.fract() != 0. {
+        bail!("PLY: Invalid face index {value}.");
+    }
+    Ok(value as u32)
+}
+
+pub fn import(path: &Path) -> Result<MeshDocument> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {path:?}"))?;
+    let name = path.file_stem().unwrap_or_default().to_string_lossy();
+    parse(&name, &data)
+}
+
+fn parse(name: &str, data: &[u8]) -> Result<MeshDocument> {
+    let (encoding, elements, offset) = parse_header(data)?;
+    let mut body = match encoding {
+        Encoding::Ascii => {
+            Body::Ascii(std::str::from_utf8(&data[offset..])?.split_ascii_whitespace())
+        }
+        _ => Body::Binary {
+            data: &data[offset..],
+            big_endian: encoding == Encoding::BigEndian,
+        },
+    };
+
+    let mut vertices = vec![];
+    let mut indices = vec![];
+    let mut has_normals = false;
+    for element in &elements {
+        for _ in 0..element.count {
+            match element.name.as_str() {
+                "vertex" => {
+                    let mut vertex = MeshVertex {
+                        position: [0.; 3],
+                        normal: [0.; 3],
+                        tex_coord: [0.; 2],
+                        color: [1.; 4],
+                    };
+                    for property in &element.properties {
+                        let Property::Scalar { name, ty } = property else {
+                            body.skip(property)?;
+                            continue;
+                        };
+                        let ty = *ty;
+                        let value = body.read(ty)?;
+                        match name.as_str() {
+                            "x" => vertex.position[0] = value as f32,
+                            "y" => vertex.position[1] = value as f32,
+                            "z" => vertex.position[2] = value as f32,
+                            "nx" => vertex.normal[0] = value as f32,
+                            "ny" => vertex.normal[1] = value as f32,
+                            "nz" => vertex.normal[2] = value as f32,
+                            "red" | "r" => vertex.color[0] = ty.normalize(value),
+                            "green" | "g" => vertex.color[1] = ty.normalize(value),
+                            "blue" | "b" => vertex.color[2] = ty.normalize(value),
+                            "alpha" | "a" => vertex.color[3] = ty.normalize(value),
+                            "s" | "u" | "texture_u" | "texture_s" => {
+                                vertex.tex_coord[0] = value as f32
+                            }
+                            // Texture origin at the bottom left, same as OBJ
+                            "t" | "v" | "texture_v" | "texture_t" => {
+                                vertex.tex_coord[1] = 1. - value as f32
+                            }
+                            _ => {}
+                        }
+                    }
+                    vertices.push(vertex);
+                }
+                "face" => {
+                    for property in &element.properties {
+                        match property {
+                            Property::List { name, count, item }
+                                if name == "vertex_indices" || name == "vertex_index" =>
+                            {
+                                let polygon = body
+                                    .read_list(*count, *item)?
+                                    .into_iter()
+                                    .map(face_index)
+                                    .collect::<Result<Vec<_>>>()?;
+                                // Fan triangulation, faces are convex in practice
+                                for i in 1..polygon.len().saturating_sub(1) {
+                                    indices.extend([polygon[0], polygon[i], polygon[i + 1]]);
+                                }
+                            }
+                            _ => body.skip(property)?,
+                        }
+                    }
+                }
+                _ => {
+                    for property in &element.properties {
+                        body.skip(property)?;
+                    }
+                }
+            }
+        }
+        if element.name == "vertex" {
+            has_normals = element
+                .properties
+                .iter()
+                .any(|property| matches!(property, Property::Scalar { name, .. } if name == "nx"));
+        }
+    }
+
+    if let Some(index) = indices
+        .iter()
+        .find(|&&index| index as usize >= vertices.len())
+    {
+        bail!(
+            "PLY: Face index {index} out of bounds of {} vertices.",
+            vertices.len()
+        );
+    }
+    let (indices, topology) = if indices.is_empty() {
+        (None, wgpu::PrimitiveTopology::PointList)
+    } else {
+        if !has_normals {
+            compute_normals(&mut vertices, &indices);
+        }
+        (
+            Some(Indices::U32(indices)),
+            wgpu::PrimitiveTopology::TriangleList,
+        )
+    };
+    Ok(MeshDocument {
+        meshes: vec![Mesh {
+            name: name.to_owned(),
+            vertices,
+            indices,
+            topology,
+            material: None,
+        }],
+        materials: vec![],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "element vertex 4
+property float x
+property float y
+property float z
+property uchar red
+property uchar green
+property uchar blue
+comment quads are split into triangles
+element face 1
+property list uchar int vertex_indices
+end_header
+";
+    const POSITIONS: [[f32; 3]; 4] = [[0., 0., 0.], [1., 0., 0.], [1., 1., 0.], [0., 1., 0.]];
+    const COLORS: [[u8; 3]; 4] = [[255, 0, 0], [0, 255, 0], [0, 0, 255], [255, 255, 255]];
+
+    fn check_quad(document: &MeshDocument) {
+        let mesh = &document.meshes[0];
+        assert_eq!(mesh.topology, wgpu::PrimitiveTopology::TriangleList);
+        assert_eq!(mesh.indices, Some(Indices::U32(vec![0, 1, 2, 0, 2, 3])));
+        assert_eq!(mesh.vertices.len(), 4);
+        for (vertex, (position, color)) in mesh.vertices.iter().zip(POSITIONS.iter().zip(COLORS)) {
+            assert_eq!(vertex.position, *position);
+            assert_eq!(
+                vertex.color,
+                [color[0], color[1], color[2], 255].map(|c| c as f32 / 255.)
+            );
+            // Computed, the file has none
+            assert_eq!(vertex.normal, [0., 0., 1.]);
+        }
+    }
+
+    fn binary(
+        format: &str,
+        to_bytes: fn(f32) -> [u8; 4],
+        index_bytes: fn(i32) -> [u8; 4],
+    ) -> Vec<u8> {
+        let mut data = format!("ply\nformat {format} 1.0\n{HEADER}").into_bytes();
+        for (position, color) in POSITIONS.iter().zip(COLORS) {
+            data.extend(position.iter().flat_map(|&v| to_bytes(v)));
+            data.extend(color);
+        }
+        data.push(4);
+        data.extend([0, 1, 2, 3].into_iter().flat_map(index_bytes));
+        data
+    }
+
+    #[test]
+    fn ascii() {
+        let body = "0 0 0 255 0 0\n1 0 0 0 255 0\n1 1 0 0 0 255\n0 1 0 255 255 255\n4 0 1 2 3\n";
+        let data = format!("ply\nformat ascii 1.0\n{HEADER}{body}");
+        check_quad(&parse("ascii", data.as_bytes()).unwrap());
+    }
+
+    #[test]
+    fn binary_little_endian() {
+        let data = binary("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes);
+        check_quad(&parse("little", &data).unwrap());
+        assert!(parse("truncated", &data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn binary_big_endian() {
+        let data = binary("binary_big_endian", f32::to_be_bytes, i32::to_be_bytes);
+        check_quad(&parse("big", &data).unwrap());
+    }
+
+    #[test]
+    fn points() {
+        let data = "ply
+format ascii 1.0
+element vertex 2
+property double x
+property double y
+property double z
+property float nx
+property float ny
+property float nz
+end_header
+0 0 0 0 1 0
+1 2 3 1 0 0
+";
+        let document = parse("points", data.as_bytes()).unwrap();
+        let mesh = &document.meshes[0];
+        assert_eq!(mesh.topology, wgpu::PrimitiveTopology::PointList);
+        assert!(mesh.indices.is_none());
+        assert_eq!(mesh.vertices[1].position, [1., 2., 3.]);
+        assert_eq!(mesh.vertices[1].normal, [1., 0., 0.]);
+        assert_eq!(mesh.vertices[1].color, [1.; 4]);
+    }
+
+    #[test]
+    fn index_out_of_bounds() {
+        let mut data = binary("binary_little_endian", f32::to_le_bytes, i32::to_le_bytes);
+        let last = data.len() - 4;
+        data[last..].copy_from_slice(&4i32.to_le_bytes());
+        assert!(parse("invalid", &data).is_err());
+
+        data[last..].copy_from_slice(&(-1i32).to_le_bytes());
+        assert!(parse("negative", &data).is_err());
+
+        let body = "0 0 0 255 0 0\n1 0 0 0 255 0\n1 1 0 0 0 255\n0 1 0 255 255 255\n4 0 1 2.5 3\n";
+        let header = HEADER.replace("uchar int vertex_indices", "uchar float vertex_indices");
+        let data = format!("ply\nformat ascii 1.0\n{header}{body}");
+        assert!(parse("fractional", data.as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,201 @@
+//! STL in binary and ASCII encodings, facets become a flat shaded triangle list.
+
+use std::path::Path;
+
+use color_eyre::{
+    eyre::{bail, ensure, Context, ContextCompat},
+    Result,
+};
+use glam::Vec3;
+
+use super::{Mesh, MeshDocument};
+use crate::app::MeshVertex;
+
+const HEADER_SIZE: usize = 84;
+const FACET_SIZE: usize = 50;
+
+struct Facet {
+    normal: [f32; 3],
+    vertices: [[f32; 3]; 3],
+}
+
+pub fn import(path: &Path) -> Result<MeshDocument> {
+    let data = std::fs::read(path).with_context(|| format!("Failed to read {path:?}"))?;
+    let name = path.file_stem().unwrap_or_default().to_string_lossy();
+    parse(&name, &data)
+}
+
+fn parse(name: &str, data: &[u8]) -> Result<MeshDocument> {
+    let facets = if is_binary(data) {
+        parse_binary(data)?
+    } else {
+        let source = std::str::from_utf8(data).context("STL: Neither binary nor ASCII.")?;
+        parse_ascii(source)?
+    };
+
+    let vertices = facets
+        .into_iter()
+        .flat_map(|Facet { normal, vertices }| {
+            let [a, b, c] = vertices.map(Vec3::from);
+            // Plenty of exporters leave facet normals zeroed
+            let normal = match Vec3::from(normal).try_normalize() {
+                Some(normal) => normal,
+                None => (b - a).cross(c - a).normalize_or_zero(),
+            };
+            vertices.map(|position| MeshVertex {
+                position,
+                normal: normal.into(),
+                tex_coord: [0.; 2],
+                color: [1.; 4],
+            })
+        })
+        .collect();
+    Ok(MeshDocument {
+        meshes: vec![Mesh {
+            name: name.to_owned(),
+            vertices,
+            indices: None,
+            topology: wgpu::PrimitiveTopology::TriangleList,
+            material: None,
+        }],
+        materials: vec![],
+    })
+}
+
+/// ASCII files start with `solid`, but so do the headers of some binary ones.
+fn is_binary(data: &[u8]) -> bool {
+    if data.len() < HEADER_SIZE {
+        return false;
+    }
+    let count = u32::from_le_bytes(data[80..84].try_into().unwrap()) as usize;
+    data.len() == HEADER_SIZE + count * FACET_SIZE || !data.starts_with(b"solid")
+}
+
+fn parse_binary(data: &[u8]) -> Result<Vec<Facet>> {
+    ensure!(data.len() >= HEADER_SIZE, "STL: Truncated header.");
+    let count = u32::from_le_bytes(data[80..84].try_into().unwrap()) as usize;
+    let facets = data[HEADER_SIZE..]
+        .get(..count * FACET_SIZE)
+        .context("STL: Truncated facets.")?;
+    Ok(facets
+        .chunks_exact(FACET_SIZE)
+        .map(|facet| {
+            let vector = |index: usize| -> [f32; 3] {
+                std::array::from_fn(|c| {
+                    bytemuck::pod_read_unaligned(&facet[(index * 3 + c) * 4..][..4])
+                })
+            };
+            Facet {
+                normal: vector(0),
+                vertices: [vector(1), vector(2), vector(3)],
+            }
+        })
+        .collect())
+}
+
+fn parse_ascii(source: &str) -> Result<Vec<Facet>> {
+    let mut tokens = source.split_ascii_whitespace();
+    let mut facets = vec![];
+    let mut normal = [0.; 3];
+    let mut vertices = vec![];
+    while let Some(token) = tokens.next() {
+        match token {
+            "facet" => {
+                ensure!(
+                    tokens.next() == Some("normal"),
+                    "STL: Expected facet normal."
+                );
+                normal = read_vector(&mut tokens)?;
+                vertices.clear();
+            }
+            "vertex" => vertices.push(read_vector(&mut tokens)?),
+            "endfacet" => {
+                let [a, b, c] = vertices[..] else {
+                    bail!("STL: Facet with {} vertices.", vertices.len());
+                };
+                facets.push(Facet {
+                    normal,
+                    vertices: [a, b, c],
+                });
+            }
+            // `solid` and `endsolid` names, `outer loop` and `endloop`
+            _ => {}
+        }
+    }
+    Ok(facets)
+}
+
+fn read_vector(tokens: &mut std::str::SplitAsciiWhitespace) -> Result<[f32; 3]> {
+    let mut vector = [0.; 3];
+    for component in &mut vector {
+        *component = tokens.next().context("STL: Truncated vector.")?.parse()?;
+    }
+    Ok(vector)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(document: &MeshDocument) -> Vec<[f32; 3]> {
+        let vertices = &document.meshes[0].vertices;
+        vertices.iter().map(|vertex| vertex.position).collect()
+    }
+
+    #[test]
+    fn binary() {
+        // Headers may start with `solid` as well
+        let mut data = b"solid binary".to_vec();
+        data.resize(80, 0);
+        data.extend(1u32.to_le_bytes());
+        // Zeroed normal, computed from the winding
+        let facet = [0., 0., 0., 0., 0., 0., 1., 0., 0., 0., 1., 0.];
+        data.extend(facet.iter().flat_map(|v: &f32| v.to_le_bytes()));
+        data.extend([0; 2]);
+
+        let document = parse("binary", &data).unwrap();
+        let mesh = &document.meshes[0];
+        assert_eq!(mesh.name, "binary");
+        assert!(mesh.indices.is_none());
+        assert_eq!(
+            positions(&document),
+            [[0., 0., 0.], [1., 0., 0.], [0., 1., 0.]]
+        );
+        assert!(mesh.vertices.iter().all(|v| v.normal == [0., 0., 1.]));
+
+        assert!(parse("truncated", &data[..data.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn ascii() {
+        let source = "solid ascii
+            facet normal 0 0 -1
+                outer loop
+                    vertex 0 0 0
+                    vertex 0 1 0
+                    vertex 1 0 0
+                endloop
+            endfacet
+            facet normal 0 0 0
+                outer loop
+                    vertex 1 0 0
+                    vertex 1 1 0
+                    vertex 0 1 0
+                endloop
+            endfacet
+        endsolid ascii
+        ";
+
+        let document = parse("ascii", source.as_bytes()).unwrap();
+        let mesh = &document.meshes[0];
+        assert_eq!(mesh.topology, wgpu::PrimitiveTopology::TriangleList);
+        assert_eq!(mesh.vertices.len(), 6);
+        assert_eq!(positions(&document)[4], [1., 1., 0.]);
+        let normals: Vec<_> = mesh.vertices.iter().map(|v| v.normal).collect();
+        assert_eq!(normals[..3], [[0., 0., -1.]; 3]);
+        assert_eq!(normals[3..], [[0., 0., 1.]; 3]);
+
+        let missing_vertex = source.replacen("vertex 1 1 0", "", 1);
+        assert!(parse("ascii", missing_vertex.as_bytes()).is_err());
+    }
+}
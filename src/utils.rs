@@ -1,8 +1,10 @@
 use std::{
     iter::{self, Repeat},
     mem::size_of,
-    num::NonZeroU64,
+    num::{NonZeroU64, NonZeroUsize},
     ops::Range,
+    sync::atomic::{AtomicUsize, Ordering},
+    thread,
     time::Duration,
 };
 
@@ -12,7 +14,7 @@ use wgpu::util::DeviceExt;
 use wgpu_profiler::GpuTimerScopeResult;
 
 pub trait NonZeroSized: Sized {
-    const NSIZE: NonZeroU64 = unsafe { NonZeroU64::new_unchecked(size_of::<Self>() as _) };
+    const NSIZE: NonZeroU64 = NonZeroU64::new(size_of::<Self>() as _).unwrap();
 }
 impl<T> NonZeroSized for T where T: Sized {}
 
@@ -51,6 +53,52 @@ where
     }
 }
 
+/// Threads used by [`par_map`], `WGLTF_THREADS` overrides the available parallelism.
+pub fn worker_count() -> usize {
+    std::env::var("WGLTF_THREADS")
+        .ok()
+        .and_then(|threads| threads.parse().ok())
+        .unwrap_or_else(|| thread::available_parallelism().map_or(1, NonZeroUsize::get))
+        .max(1)
+}
+
+/// Maps items on scoped threads keeping their order. Workers pull items one by one,
+/// so a few expensive items don't leave the other threads idle.
+pub fn par_map<T: Sync, R: Send>(items: &[T], f: impl Fn(&T) -> R + Sync) -> Vec<R> {
+    let threads = worker_count().min(items.len());
+    if threads <= 1 {
+        return items.iter().map(f).collect();
+    }
+
+    let next = AtomicUsize::new(0);
+    let mut results: Vec<(usize, R)> = thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads)
+            .map(|_| {
+                scope.spawn(|| {
+                    let mut results = vec![];
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(item) = items.get(index) else {
+                            break results;
+                        };
+                        results.push((index, f(item)));
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect()
+    });
+    results.sort_unstable_by_key(|(index, _)| *index);
+    results.into_iter().map(|(_, result)| result).collect()
+}
+
 pub fn scopes_to_console_recursive(results: &[GpuTimerScopeResult], indentation: usize) {
     for scope in results {
         if indentation > 0 {